@@ -0,0 +1,345 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use lsp_types::{Diagnostic, Range};
+use serde::{Deserialize, Serialize};
+use tan::expr::Expr;
+use tan_lints::compute_diagnostics;
+use tracing::trace;
+
+use crate::{
+    config::EvalConfig,
+    eval::{make_sandboxed_context, Cancellation, EvalTimeout},
+    sandbox::{run_cancellable, SandboxJob},
+    util::{lsp_range_from_tan_range, parse_module_file, parse_string_all},
+};
+
+// #insight
+// Analysis evaluates the top-level `let` definitions of a module, the
+// evaluation runs in a worker process, in a sandboxed context without IO
+// foreign functions. Only plain data leaves the worker, see `sandbox`.
+
+// #insight
+// The server analyzes documents in the background, with the `AnalysisQueue`,
+// so that a slow (or looping) module does not block the main loop. Analyses
+// are debounced, the next change of a document cancels the pending analysis.
+
+/// The delay before a scheduled analysis starts.
+pub const ANALYSIS_DEBOUNCE: Duration = Duration::from_millis(150);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalyzedBinding {
+    pub name: String,
+    pub range: Option<Range>,
+    /// The dynamic type of the bound value, if it can be inferred.
+    pub typ: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ModuleAnalysis {
+    pub bindings: Vec<AnalyzedBinding>,
+    /// Compile and eval errors, parse errors are reported by the lints.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn analyze_module_sync(
+    exprs: &[Expr],
+    document_uri: &str,
+    search_paths: &[PathBuf],
+    sandbox: bool,
+) -> ModuleAnalysis {
+    // #insight the output of the analyzed module is discarded.
+    let (output_sender, _) = unbounded();
+
+    let Ok(mut context) = make_sandboxed_context(document_uri, output_sender, sandbox) else {
+        return ModuleAnalysis::default();
+    };
+
+    let (scope, errors) = parse_module_file(exprs, &mut context, search_paths);
+
+    let mut bindings: Vec<AnalyzedBinding> = scope
+        .bindings
+        .read()
+        .expect("not poisoned")
+        .iter()
+        .map(|(name, expr)| AnalyzedBinding {
+            name: name.clone(),
+            range: expr.range().map(lsp_range_from_tan_range),
+            typ: match expr.dyn_type(&context) {
+                Expr::Type(typ) => Some(typ),
+                _ => None,
+            },
+        })
+        .collect();
+
+    // #insight the scope is unordered, sort in source order for stable results.
+    bindings.sort_by_key(|binding| {
+        (
+            binding
+                .range
+                .as_ref()
+                .map(|range| (range.start.line, range.start.character)),
+            binding.name.clone(),
+        )
+    });
+
+    let diagnostics = if errors.is_empty() {
+        Vec::new()
+    } else {
+        compute_diagnostics(&Err(errors))
+    };
+
+    ModuleAnalysis {
+        bindings,
+        diagnostics,
+    }
+}
+
+/// Parses and analyzes a module, runs in the worker process, see
+/// `SandboxJob`. A module with parse errors is not analyzed.
+pub fn analyze_input_sync(
+    input: &str,
+    document_uri: &str,
+    search_paths: &[PathBuf],
+    sandbox: bool,
+) -> ModuleAnalysis {
+    match parse_string_all(input) {
+        Ok(exprs) => analyze_module_sync(&exprs, document_uri, search_paths, sandbox),
+        Err(_) => ModuleAnalysis::default(),
+    }
+}
+
+/// Analyzes a module in a sandboxed worker process, with a time limit. The
+/// worker is killed on timeout, or once `cancellation` is set.
+pub fn analyze_module(
+    input: String,
+    document_uri: String,
+    search_paths: Vec<PathBuf>,
+    config: &EvalConfig,
+    cancellation: &Cancellation,
+) -> Result<ModuleAnalysis, EvalTimeout> {
+    let job = SandboxJob::Analyze {
+        input,
+        document_uri,
+        search_paths,
+        sandbox: config.sandbox,
+    };
+
+    let analysis = run_cancellable(&job, config.analysis_timeout(), cancellation, |_| {})?;
+
+    Ok(analysis.unwrap_or_else(|| {
+        trace!("analysis worker cancelled or failed");
+        ModuleAnalysis::default()
+    }))
+}
+
+struct AnalysisJob {
+    generation: u64,
+    cancellation: Cancellation,
+}
+
+/// The result of a scheduled analysis.
+pub struct AnalysisResult {
+    pub uri: String,
+    pub generation: u64,
+    pub analysis: Result<ModuleAnalysis, EvalTimeout>,
+}
+
+/// Analyzes documents in the background, at most one analysis per document.
+pub struct AnalysisQueue {
+    sender: Sender<AnalysisResult>,
+    receiver: Receiver<AnalysisResult>,
+    jobs: HashMap<String, AnalysisJob>,
+    next_generation: u64,
+}
+
+impl Default for AnalysisQueue {
+    fn default() -> Self {
+        let (sender, receiver) = unbounded();
+
+        Self {
+            sender,
+            receiver,
+            jobs: HashMap::new(),
+            next_generation: 0,
+        }
+    }
+}
+
+impl AnalysisQueue {
+    /// The results of the scheduled analyses.
+    pub fn receiver(&self) -> &Receiver<AnalysisResult> {
+        &self.receiver
+    }
+
+    /// Schedules the analysis of a document, after `ANALYSIS_DEBOUNCE`. The
+    /// previous analysis of the document is cancelled.
+    pub fn schedule(
+        &mut self,
+        input: String,
+        document_uri: String,
+        search_paths: Vec<PathBuf>,
+        config: &EvalConfig,
+    ) {
+        self.cancel(&document_uri);
+
+        self.next_generation += 1;
+
+        let job = AnalysisJob {
+            generation: self.next_generation,
+            cancellation: Cancellation::default(),
+        };
+
+        let sender = self.sender.clone();
+        let generation = job.generation;
+        let cancellation = job.cancellation.clone();
+        let config = config.clone();
+        let uri = document_uri.clone();
+
+        std::thread::spawn(move || {
+            std::thread::sleep(ANALYSIS_DEBOUNCE);
+
+            if cancellation.is_cancelled() {
+                return;
+            }
+
+            let analysis = analyze_module(input, uri.clone(), search_paths, &config, &cancellation);
+
+            let _ = sender.send(AnalysisResult {
+                uri,
+                generation,
+                analysis,
+            });
+        });
+
+        self.jobs.insert(document_uri, job);
+    }
+
+    /// Marks the analysis as completed, returns false if it is superseded by a
+    /// newer analysis of the document, or cancelled.
+    pub fn complete(&mut self, result: &AnalysisResult) -> bool {
+        match self.jobs.get(&result.uri) {
+            Some(job) if job.generation == result.generation => {
+                self.jobs.remove(&result.uri);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Cancels the pending or running analysis of a document.
+    pub fn cancel(&mut self, document_uri: &str) {
+        if let Some(job) = self.jobs.remove(document_uri) {
+            job.cancellation.cancel();
+        }
+    }
+
+    /// Cancels all pending and running analyses.
+    pub fn cancel_all(&mut self) {
+        for (_, job) in self.jobs.drain() {
+            job.cancellation.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        analysis::{analyze_module, AnalysisQueue, ANALYSIS_DEBOUNCE},
+        config::EvalConfig,
+        eval::Cancellation,
+    };
+
+    #[test]
+    fn analyze_module_usage() {
+        let input = r#"
+        (let a 1)
+        (let zonk (Func [x y] (+ x y)))
+        (let b (undefined-func a))
+        "#;
+
+        let analysis = analyze_module(
+            input.to_string(),
+            String::from("file:///project/main.tan"),
+            Vec::new(),
+            &EvalConfig::default(),
+            &Cancellation::default(),
+        )
+        .unwrap();
+
+        let zonk = analysis.bindings.iter().find(|b| b.name == "zonk").unwrap();
+        assert_eq!(zonk.typ.as_deref(), Some("Func"));
+        assert!(!analysis.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn analyze_module_times_out() {
+        let input = r#"
+        (let loop-forever (Func [] (loop-forever)))
+        (let a (loop-forever))
+        "#;
+
+        let cancellation = Cancellation::default();
+        let analysis = analyze_module(
+            input.to_string(),
+            String::from("file:///project/main.tan"),
+            Vec::new(),
+            &EvalConfig {
+                analysis_timeout_ms: 200,
+                ..Default::default()
+            },
+            &cancellation,
+        );
+        // #insight the worker is killed on timeout, or it overflows its stack
+        // first, in any case the server is not blocked.
+        assert!(analysis.map_or(true, |analysis| analysis.bindings.is_empty()));
+    }
+
+    #[test]
+    fn analysis_queue_usage() {
+        let mut queue = AnalysisQueue::default();
+        let uri = String::from("file:///project/main.tan");
+
+        queue.schedule(
+            String::from("(let a 1)"),
+            uri.clone(),
+            Vec::new(),
+            &EvalConfig::default(),
+        );
+        // #insight the next change supersedes the pending analysis.
+        queue.schedule(
+            String::from("(let a 1)\n(let b (undefined-func a))"),
+            uri.clone(),
+            Vec::new(),
+            &EvalConfig::default(),
+        );
+
+        let result = queue
+            .receiver()
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(result.uri, uri);
+        assert!(queue.complete(&result));
+        assert!(!result.analysis.unwrap().diagnostics.is_empty());
+
+        // #insight the superseded analysis was cancelled before it started.
+        assert!(queue
+            .receiver()
+            .recv_timeout(ANALYSIS_DEBOUNCE * 2)
+            .is_err());
+
+        queue.schedule(
+            String::from("(let a 1)"),
+            uri.clone(),
+            Vec::new(),
+            &EvalConfig::default(),
+        );
+        queue.cancel_all();
+        assert!(queue
+            .receiver()
+            .recv_timeout(ANALYSIS_DEBOUNCE * 2)
+            .is_err());
+    }
+}
@@ -0,0 +1,407 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, Position, Range,
+    SymbolKind, Uri,
+};
+use tan::expr::Expr;
+
+use crate::{
+    imports::{find_use_imports, module_prefix, resolve_module_path},
+    inlay_hints::func_params,
+    util::{
+        document_module_path, is_position_in_range, let_bindings, lsp_range_from_tan_range,
+        path_from_uri,
+    },
+};
+
+// #insight
+// The call hierarchy is computed statically from the AST, a function is a
+// top-level `let` binding with a `Func` value. A call site is resolved by the
+// uri of the caller: a bare name to the definitions of the same document, then
+// of the same module (directory), a qualified name (e.g. `math/add`) to the
+// definitions of the imported module.
+
+/// A function definition, i.e. a top-level `(let name (Func ...))`.
+pub struct FuncDefinition<'a> {
+    pub name: String,
+    pub uri: &'a Uri,
+    pub range: Range,
+    pub selection_range: Range,
+    pub params: Vec<String>,
+    pub body: &'a Expr,
+}
+
+impl FuncDefinition<'_> {
+    pub fn to_item(&self) -> CallHierarchyItem {
+        CallHierarchyItem {
+            name: self.name.clone(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            detail: Some(format!("({} {})", self.name, self.params.join(" "))),
+            uri: self.uri.clone(),
+            range: self.range,
+            selection_range: self.selection_range,
+            data: None,
+        }
+    }
+}
+
+pub fn find_func_definitions<'a>(uri: &'a Uri, exprs: &'a [Expr]) -> Vec<FuncDefinition<'a>> {
+    let mut definitions = Vec::new();
+
+    for expr in exprs {
+        let Some(expr_range) = expr.range() else {
+            continue;
+        };
+
+        for (name, value) in let_bindings(expr) {
+            let (Some(sym), Some(name_range)) = (name.as_symbol(), name.range()) else {
+                continue;
+            };
+
+            let Some(params) = func_params(value) else {
+                continue;
+            };
+
+            definitions.push(FuncDefinition {
+                name: sym.to_string(),
+                uri,
+                range: lsp_range_from_tan_range(expr_range),
+                selection_range: lsp_range_from_tan_range(name_range),
+                params,
+                body: value,
+            });
+        }
+    }
+
+    definitions
+}
+
+/// Collects the call sites within an expression, as `(callee, range)` pairs,
+/// the range is the range of the callee symbol.
+pub fn find_calls(expr: &Expr, calls: &mut Vec<(String, Range)>) {
+    let Some(terms) = expr.as_list() else {
+        return;
+    };
+
+    if let Some(op) = terms.first() {
+        if let (Some(sym), Some(range)) = (op.as_symbol(), op.range()) {
+            calls.push((sym.to_string(), lsp_range_from_tan_range(range)));
+        }
+    }
+
+    for term in terms {
+        find_calls(term, calls);
+    }
+}
+
+/// The top-level calls of a document, i.e. the calls outside of function
+/// definitions.
+pub fn find_top_level_calls(exprs: &[Expr], calls: &mut Vec<(String, Range)>) {
+    for expr in exprs {
+        let bindings = let_bindings(expr);
+
+        if bindings.is_empty() {
+            find_calls(expr, calls);
+            continue;
+        }
+
+        for (_, value) in bindings {
+            if func_params(value).is_none() {
+                find_calls(value, calls);
+            }
+        }
+    }
+}
+
+/// The function definitions of the workspace, with the imports of every
+/// source, to resolve call sites.
+pub struct CallGraph<'a> {
+    sources: &'a [(Uri, &'a [Expr])],
+    definitions: Vec<FuncDefinition<'a>>,
+    /// The resolved module paths of the imports of each source, by prefix.
+    imports: HashMap<String, HashMap<String, PathBuf>>,
+}
+
+fn module_dir(uri: &Uri) -> Option<PathBuf> {
+    path_from_uri(uri.as_str())?.parent().map(Path::to_path_buf)
+}
+
+fn is_same_definition(a: &FuncDefinition, b: &FuncDefinition) -> bool {
+    a.uri == b.uri && a.selection_range == b.selection_range
+}
+
+impl<'a> CallGraph<'a> {
+    pub fn new(sources: &'a [(Uri, &'a [Expr])], search_paths: &[PathBuf]) -> Self {
+        let definitions = sources
+            .iter()
+            .flat_map(|(uri, exprs)| find_func_definitions(uri, exprs))
+            .collect();
+
+        let imports = sources
+            .iter()
+            .map(|(uri, exprs)| {
+                let mut modules = HashMap::new();
+                if let Ok(module_path) = document_module_path(uri.as_str()) {
+                    for import in find_use_imports(exprs) {
+                        if let Some(path) =
+                            resolve_module_path(&import.path, &module_path, search_paths)
+                        {
+                            modules.insert(module_prefix(&import.path).to_string(), path);
+                        }
+                    }
+                }
+                (uri.to_string(), modules)
+            })
+            .collect();
+
+        Self {
+            sources,
+            definitions,
+            imports,
+        }
+    }
+
+    /// Returns the definition of the given item.
+    pub fn find_definition(&self, item: &CallHierarchyItem) -> Option<&FuncDefinition<'a>> {
+        self.definitions.iter().find(|definition| {
+            *definition.uri == item.uri && definition.selection_range == item.selection_range
+        })
+    }
+
+    /// Resolves the callee of a call site in the document `uri`.
+    pub fn resolve(&self, uri: &Uri, callee: &str) -> Option<&FuncDefinition<'a>> {
+        if let Some(definition) = self
+            .definitions
+            .iter()
+            .find(|definition| definition.uri == uri && definition.name == callee)
+        {
+            return Some(definition);
+        }
+
+        if let Some((prefix, name)) = callee.rsplit_once('/') {
+            let module_path = self.imports.get(uri.as_str())?.get(prefix)?;
+            return self.definitions.iter().find(|definition| {
+                definition.name == name
+                    && path_from_uri(definition.uri.as_str())
+                        .is_some_and(|path| path.starts_with(module_path))
+            });
+        }
+
+        let dir = module_dir(uri)?;
+        self.definitions.iter().find(|definition| {
+            definition.name == callee && module_dir(definition.uri).as_ref() == Some(&dir)
+        })
+    }
+}
+
+/// An item for the top-level forms of a document.
+fn top_level_item(uri: &Uri, range: Range) -> CallHierarchyItem {
+    let name = path_from_uri(uri.as_str())
+        .and_then(|path| Some(path.file_name()?.to_string_lossy().to_string()))
+        .unwrap_or_else(|| uri.to_string());
+
+    CallHierarchyItem {
+        name,
+        kind: SymbolKind::FILE,
+        tags: None,
+        detail: Some(String::from("top-level")),
+        uri: uri.clone(),
+        range,
+        selection_range: range,
+        data: None,
+    }
+}
+
+pub fn prepare_call_hierarchy(
+    graph: &CallGraph,
+    uri: &Uri,
+    exprs: &[Expr],
+    position: Position,
+) -> Option<Vec<CallHierarchyItem>> {
+    // #insight the cursor is on the name of a function definition.
+    if let Some(definition) = graph.definitions.iter().find(|definition| {
+        definition.uri == uri && is_position_in_range(position, definition.selection_range)
+    }) {
+        return Some(vec![definition.to_item()]);
+    }
+
+    // #insight the cursor is on a call site.
+    let mut calls = Vec::new();
+    for expr in exprs {
+        find_calls(expr, &mut calls);
+    }
+
+    let (callee, _) = calls
+        .into_iter()
+        .find(|(_, range)| is_position_in_range(position, *range))?;
+
+    let definition = graph.resolve(uri, &callee)?;
+
+    Some(vec![definition.to_item()])
+}
+
+pub fn incoming_calls(
+    graph: &CallGraph,
+    item: &CallHierarchyItem,
+) -> Vec<CallHierarchyIncomingCall> {
+    let Some(target) = graph.find_definition(item) else {
+        return Vec::new();
+    };
+
+    let mut incoming = Vec::new();
+
+    let calls_target = |uri: &Uri, calls: Vec<(String, Range)>| -> Vec<Range> {
+        calls
+            .into_iter()
+            .filter(|(callee, _)| {
+                graph
+                    .resolve(uri, callee)
+                    .is_some_and(|definition| is_same_definition(definition, target))
+            })
+            .map(|(_, range)| range)
+            .collect()
+    };
+
+    for definition in &graph.definitions {
+        let mut calls = Vec::new();
+        find_calls(definition.body, &mut calls);
+
+        let from_ranges = calls_target(definition.uri, calls);
+
+        if !from_ranges.is_empty() {
+            incoming.push(CallHierarchyIncomingCall {
+                from: definition.to_item(),
+                from_ranges,
+            });
+        }
+    }
+
+    // #insight the top-level calls of a document are grouped in a file item.
+    for (uri, exprs) in graph.sources {
+        let mut calls = Vec::new();
+        find_top_level_calls(exprs, &mut calls);
+
+        let from_ranges = calls_target(uri, calls);
+
+        if let (Some(first), Some(last)) = (from_ranges.first(), from_ranges.last()) {
+            incoming.push(CallHierarchyIncomingCall {
+                from: top_level_item(uri, Range::new(first.start, last.end)),
+                from_ranges,
+            });
+        }
+    }
+
+    incoming
+}
+
+pub fn outgoing_calls(
+    graph: &CallGraph,
+    item: &CallHierarchyItem,
+) -> Vec<CallHierarchyOutgoingCall> {
+    let Some(definition) = graph.find_definition(item) else {
+        return Vec::new();
+    };
+
+    let mut calls = Vec::new();
+    find_calls(definition.body, &mut calls);
+
+    // #insight group the call sites by callee, preserving the call order.
+    let mut outgoing: Vec<CallHierarchyOutgoingCall> = Vec::new();
+
+    for (callee, range) in calls {
+        let Some(to) = graph.resolve(definition.uri, &callee) else {
+            continue;
+        };
+
+        let to = to.to_item();
+
+        if let Some(call) = outgoing
+            .iter_mut()
+            .find(|call| call.to.uri == to.uri && call.to.selection_range == to.selection_range)
+        {
+            call.from_ranges.push(range);
+        } else {
+            outgoing.push(CallHierarchyOutgoingCall {
+                to,
+                from_ranges: vec![range],
+            });
+        }
+    }
+
+    outgoing
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use lsp_types::{SymbolKind, Uri};
+
+    use crate::{
+        call_hierarchy::{incoming_calls, outgoing_calls, CallGraph},
+        util::parse_string_all,
+    };
+
+    #[test]
+    fn call_hierarchy_usage() {
+        let input = r#"
+        (let square (Func [x] (* x x)))
+        (let sum-of-squares (Func [a b] (+ (square a) (square b))))
+        (let main (Func [] (writeln (sum-of-squares 1 2))))
+        (writeln (square 3))
+        "#;
+
+        let uri = Uri::from_str("file:///project/main.tan").unwrap();
+        let exprs = parse_string_all(input).unwrap();
+        let sources = vec![(uri.clone(), exprs.as_slice())];
+        let graph = CallGraph::new(&sources, &[]);
+        assert_eq!(graph.definitions.len(), 3);
+
+        let square = graph.definitions[0].to_item();
+        let incoming = incoming_calls(&graph, &square);
+        assert_eq!(incoming.len(), 2);
+        assert_eq!(incoming[0].from.name, "sum-of-squares");
+        assert_eq!(incoming[0].from_ranges.len(), 2);
+        // #insight the top-level call.
+        assert_eq!(incoming[1].from.name, "main.tan");
+        assert_eq!(incoming[1].from.kind, SymbolKind::FILE);
+
+        let main = graph.definitions[2].to_item();
+        let outgoing = outgoing_calls(&graph, &main);
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].to.name, "sum-of-squares");
+    }
+
+    #[test]
+    fn call_hierarchy_resolves_by_module() {
+        let main_uri = Uri::from_str("file:///project/main.tan").unwrap();
+        let main_exprs = parse_string_all("(let main (Func [] (helper)))\n").unwrap();
+
+        // #insight same-named functions in different modules.
+        let util_uri = Uri::from_str("file:///project/util.tan").unwrap();
+        let util_exprs = parse_string_all("(let helper (Func [] 1))\n").unwrap();
+        let other_uri = Uri::from_str("file:///other/lib.tan").unwrap();
+        let other_exprs = parse_string_all("(let helper (Func [] 2))\n").unwrap();
+
+        let sources = vec![
+            (main_uri.clone(), main_exprs.as_slice()),
+            (util_uri.clone(), util_exprs.as_slice()),
+            (other_uri.clone(), other_exprs.as_slice()),
+        ];
+        let graph = CallGraph::new(&sources, &[]);
+
+        let main = graph.definitions[0].to_item();
+        let outgoing = outgoing_calls(&graph, &main);
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].to.uri, util_uri);
+
+        let other_helper = graph.definitions[2].to_item();
+        assert_eq!(other_helper.uri, other_uri);
+        assert!(incoming_calls(&graph, &other_helper).is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use lsp_types::{ClientCapabilities, MarkupKind, PositionEncodingKind};
+
+// #insight
+// The server adapts its responses to the capabilities of the client, e.g.
+// returns nested document symbols only if the client supports them.
+
+#[derive(Debug, Clone)]
+pub struct NegotiatedCapabilities {
+    pub position_encoding: PositionEncodingKind,
+    /// Nested `DocumentSymbol`s, instead of flat `SymbolInformation`s.
+    pub hierarchical_document_symbols: bool,
+    /// Markdown in tooltips.
+    pub markdown: bool,
+    /// Snippets in completion items.
+    pub snippets: bool,
+    /// The client pulls diagnostics with `textDocument/diagnostic`.
+    pub pull_diagnostics: bool,
+    /// The client supports `workspace/diagnostic/refresh`.
+    pub diagnostic_refresh: bool,
+    /// The client supports `workspace/configuration` pulls.
+    pub configuration_pull: bool,
+    /// The client supports the dynamic registration of file watchers.
+    pub watched_files_registration: bool,
+    /// The client supports server-initiated work-done progress.
+    pub work_done_progress: bool,
+}
+
+impl Default for NegotiatedCapabilities {
+    fn default() -> Self {
+        Self {
+            position_encoding: PositionEncodingKind::UTF16,
+            hierarchical_document_symbols: false,
+            markdown: false,
+            snippets: false,
+            pull_diagnostics: false,
+            diagnostic_refresh: false,
+            configuration_pull: false,
+            watched_files_registration: false,
+            work_done_progress: false,
+        }
+    }
+}
+
+/// Negotiates the position encoding, the first supported encoding of the
+/// client's preferences is used. UTF-16 is mandatory, i.e. the default.
+fn negotiate_position_encoding(capabilities: &ClientCapabilities) -> PositionEncodingKind {
+    let supported = [
+        PositionEncodingKind::UTF8,
+        PositionEncodingKind::UTF16,
+        PositionEncodingKind::UTF32,
+    ];
+
+    capabilities
+        .general
+        .as_ref()
+        .and_then(|general| general.position_encodings.as_ref())
+        .and_then(|encodings| {
+            encodings
+                .iter()
+                .find(|encoding| supported.contains(encoding))
+        })
+        .cloned()
+        .unwrap_or(PositionEncodingKind::UTF16)
+}
+
+impl NegotiatedCapabilities {
+    pub fn from_client(capabilities: &ClientCapabilities) -> Self {
+        let text_document = capabilities.text_document.as_ref();
+        let workspace = capabilities.workspace.as_ref();
+
+        Self {
+            position_encoding: negotiate_position_encoding(capabilities),
+            hierarchical_document_symbols: text_document
+                .and_then(|caps| caps.document_symbol.as_ref())
+                .and_then(|caps| caps.hierarchical_document_symbol_support)
+                .unwrap_or(false),
+            markdown: text_document
+                .and_then(|caps| caps.hover.as_ref())
+                .and_then(|caps| caps.content_format.as_ref())
+                .is_some_and(|formats| formats.contains(&MarkupKind::Markdown)),
+            snippets: text_document
+                .and_then(|caps| caps.completion.as_ref())
+                .and_then(|caps| caps.completion_item.as_ref())
+                .and_then(|caps| caps.snippet_support)
+                .unwrap_or(false),
+            pull_diagnostics: text_document
+                .and_then(|caps| caps.diagnostic.as_ref())
+                .is_some(),
+            diagnostic_refresh: workspace
+                .and_then(|caps| caps.diagnostic.as_ref())
+                .and_then(|caps| caps.refresh_support)
+                .unwrap_or(false),
+            configuration_pull: workspace
+                .and_then(|caps| caps.configuration)
+                .unwrap_or(false),
+            watched_files_registration: workspace
+                .and_then(|caps| caps.did_change_watched_files.as_ref())
+                .and_then(|caps| caps.dynamic_registration)
+                .unwrap_or(false),
+            work_done_progress: capabilities
+                .window
+                .as_ref()
+                .and_then(|caps| caps.work_done_progress)
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{ClientCapabilities, PositionEncodingKind};
+    use serde_json::json;
+
+    use crate::capabilities::NegotiatedCapabilities;
+
+    #[test]
+    fn negotiated_capabilities_usage() {
+        let capabilities: ClientCapabilities = serde_json::from_value(json!({
+            "textDocument": {
+                "documentSymbol": { "hierarchicalDocumentSymbolSupport": true },
+                "hover": { "contentFormat": ["markdown", "plaintext"] },
+                "completion": { "completionItem": { "snippetSupport": true } },
+            },
+            "workspace": { "configuration": true },
+            "window": { "workDoneProgress": true },
+            "general": { "positionEncodings": ["utf-8", "utf-16"] },
+        }))
+        .unwrap();
+
+        let negotiated = NegotiatedCapabilities::from_client(&capabilities);
+        assert!(negotiated.hierarchical_document_symbols);
+        assert!(negotiated.markdown);
+        assert!(negotiated.snippets);
+        assert!(negotiated.configuration_pull);
+        assert!(!negotiated.pull_diagnostics);
+        assert!(!negotiated.watched_files_registration);
+        assert!(negotiated.work_done_progress);
+        assert_eq!(negotiated.position_encoding, PositionEncodingKind::UTF8);
+
+        let negotiated = NegotiatedCapabilities::from_client(&ClientCapabilities::default());
+        assert!(!negotiated.hierarchical_document_symbols);
+        assert!(!negotiated.snippets);
+        assert_eq!(negotiated.position_encoding, PositionEncodingKind::UTF16);
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::ValueEnum;
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, PositionEncodingKind, Range};
+use serde_json::{json, Value};
+
+use crate::{
+    cli::CheckArgs,
+    config::ServerConfig,
+    diagnostics::{compute_document_diagnostics, is_error},
+    line_index::LineIndex,
+    project_config::load_project_server_config,
+    util::{lsp_range_top, parse_string_all, uri_from_path, VERSION},
+    workspace::workspace_files,
+};
+
+// #insight
+// The `check` subcommand reports the diagnostics of the editor, for CI. The
+// current directory is the workspace root, i.e. the project configuration is
+// read from `./tan.config.tan`. The human output columns count characters,
+// the JSON and SARIF positions are UTF-16 positions, like LSP.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Human,
+    Json,
+    Sarif,
+}
+
+#[derive(Debug)]
+pub struct FileDiagnostics {
+    pub path: PathBuf,
+    pub input: String,
+    /// The diagnostics, with character positions.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl FileDiagnostics {
+    /// A file that cannot be checked, e.g. an unreadable file, the error is
+    /// reported as a diagnostic of the file.
+    pub fn from_error(path: &Path, error: &anyhow::Error) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            input: String::new(),
+            diagnostics: vec![Diagnostic {
+                range: lsp_range_top(),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some(String::from("tan")),
+                message: format!("cannot check the file: {error}"),
+                ..Default::default()
+            }],
+        }
+    }
+
+    /// The diagnostics, with UTF-16 positions.
+    pub fn utf16_diagnostics(&self) -> Vec<Diagnostic> {
+        let index = LineIndex::new(&self.input);
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| Diagnostic {
+                range: Range::new(
+                    index.encode(diagnostic.range.start, &PositionEncodingKind::UTF16),
+                    index.encode(diagnostic.range.end, &PositionEncodingKind::UTF16),
+                ),
+                ..diagnostic.clone()
+            })
+            .collect()
+    }
+}
+
+/// Expands the given paths, directories are scanned for `.tan` files.
+pub fn expand_paths(paths: &[PathBuf], config: &ServerConfig) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .flat_map(|path| {
+            if path.is_dir() {
+                workspace_files(path, &config.workspace)
+            } else {
+                vec![path.clone()]
+            }
+        })
+        .collect()
+}
+
+pub fn check_file(
+    path: &Path,
+    config: &ServerConfig,
+    search_paths: &[PathBuf],
+) -> anyhow::Result<FileDiagnostics> {
+    let input = std::fs::read_to_string(path)?;
+    let uri = uri_from_path(&path.canonicalize()?)
+        .ok_or_else(|| anyhow::anyhow!("invalid path `{}`", path.display()))?;
+
+    let parse_result = parse_string_all(&input);
+    let diagnostics = compute_document_diagnostics(
+        uri.as_str(),
+        &input,
+        &parse_result,
+        config,
+        search_paths.to_vec(),
+    )?;
+
+    Ok(FileDiagnostics {
+        path: path.to_path_buf(),
+        input,
+        diagnostics,
+    })
+}
+
+/// Checks the files, a file that cannot be checked does not stop the check.
+pub fn check_files(
+    paths: &[PathBuf],
+    config: &ServerConfig,
+    search_paths: &[PathBuf],
+) -> Vec<FileDiagnostics> {
+    paths
+        .iter()
+        .map(|path| {
+            check_file(path, config, search_paths)
+                .unwrap_or_else(|error| FileDiagnostics::from_error(path, &error))
+        })
+        .collect()
+}
+
+fn severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "error",
+    }
+}
+
+fn code_string(code: &Option<NumberOrString>) -> Option<String> {
+    match code {
+        Some(NumberOrString::String(code)) => Some(code.clone()),
+        Some(NumberOrString::Number(code)) => Some(code.to_string()),
+        None => None,
+    }
+}
+
+pub fn format_human(files: &[FileDiagnostics]) -> String {
+    let mut output = String::new();
+
+    for file in files {
+        for diagnostic in &file.diagnostics {
+            let start = diagnostic.range.start;
+            let code = code_string(&diagnostic.code)
+                .map(|code| format!("[{code}]"))
+                .unwrap_or_default();
+            output.push_str(&format!(
+                "{}:{}:{}: {}{}: {}\n",
+                file.path.display(),
+                start.line + 1,
+                start.character + 1,
+                severity_name(diagnostic.severity),
+                code,
+                diagnostic.message
+            ));
+        }
+    }
+
+    let errors = files
+        .iter()
+        .flat_map(|file| &file.diagnostics)
+        .filter(|diagnostic| is_error(diagnostic))
+        .count();
+    let total = files
+        .iter()
+        .map(|file| file.diagnostics.len())
+        .sum::<usize>();
+    output.push_str(&format!(
+        "{} files checked, {} errors, {} other diagnostics\n",
+        files.len(),
+        errors,
+        total - errors
+    ));
+
+    output
+}
+
+pub fn format_json(files: &[FileDiagnostics]) -> Value {
+    Value::Array(
+        files
+            .iter()
+            .map(|file| {
+                json!({
+                    "path": file.path,
+                    "diagnostics": file.utf16_diagnostics(),
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Formats the diagnostics as a SARIF 2.1.0 log, e.g. for GitHub code
+/// scanning.
+pub fn format_sarif(files: &[FileDiagnostics]) -> Value {
+    let mut rules: Vec<String> = files
+        .iter()
+        .flat_map(|file| &file.diagnostics)
+        .filter_map(|diagnostic| code_string(&diagnostic.code))
+        .collect();
+    rules.sort();
+    rules.dedup();
+
+    let results: Vec<Value> = files
+        .iter()
+        .flat_map(|file| {
+            file.utf16_diagnostics().into_iter().map(|diagnostic| {
+                let level = match diagnostic.severity {
+                    Some(DiagnosticSeverity::WARNING) => "warning",
+                    Some(DiagnosticSeverity::INFORMATION) | Some(DiagnosticSeverity::HINT) => {
+                        "note"
+                    }
+                    _ => "error",
+                };
+
+                // #insight SARIF lines and columns are 1-based, columns count UTF-16 code units by default.
+                let range = diagnostic.range;
+                let mut result = json!({
+                    "level": level,
+                    "message": { "text": diagnostic.message },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": {
+                                "uri": file.path.to_string_lossy().replace('\\', "/"),
+                            },
+                            "region": {
+                                "startLine": range.start.line + 1,
+                                "startColumn": range.start.character + 1,
+                                "endLine": range.end.line + 1,
+                                "endColumn": range.end.character + 1,
+                            },
+                        },
+                    }],
+                });
+
+                if let Some(code) = code_string(&diagnostic.code) {
+                    result["ruleId"] = Value::String(code);
+                }
+
+                result
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "tan-language-server",
+                    "version": VERSION,
+                    "informationUri": "https://github.com/tan-language/tan-language-server",
+                    "rules": rules.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// Checks the given paths, exits with failure if there are errors.
+pub fn run_check(args: &CheckArgs) -> anyhow::Result<ExitCode> {
+    let root = std::env::current_dir()?;
+    let config = load_project_server_config(&root).map_err(anyhow::Error::msg)?;
+    let search_paths = config.module_search_paths(Some(&root));
+
+    let paths = if args.paths.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        args.paths.clone()
+    };
+
+    let files = check_files(&expand_paths(&paths, &config), &config, &search_paths);
+
+    let output = match args.format {
+        OutputFormat::Human => format_human(&files),
+        OutputFormat::Json => serde_json::to_string_pretty(&format_json(&files))?,
+        OutputFormat::Sarif => serde_json::to_string_pretty(&format_sarif(&files))?,
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", output.trim_end())?;
+
+    let has_errors = files
+        .iter()
+        .flat_map(|file| &file.diagnostics)
+        .any(is_error);
+
+    Ok(if has_errors {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        check::{check_file, check_files, format_human, format_sarif},
+        config::ServerConfig,
+        diagnostics::is_error,
+    };
+
+    #[test]
+    fn check_file_usage() {
+        let dir = std::env::temp_dir().join(format!("tan-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.tan");
+        std::fs::write(&path, "(let a (+ 1 2)\n").unwrap();
+
+        let file = check_file(&path, &ServerConfig::default(), &[]).unwrap();
+        assert!(file.diagnostics.iter().any(is_error));
+
+        let files = [file];
+        assert!(format_human(&files).contains("main.tan:"));
+
+        let sarif = format_sarif(&files);
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(sarif["runs"][0]["results"][0]["level"], "error");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_files_reports_unreadable_files() {
+        let dir = std::env::temp_dir().join(format!("tan-check-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.tan");
+        std::fs::write(&path, "(let a 1)\n").unwrap();
+        let missing = dir.join("missing.tan");
+
+        let files = check_files(&[missing, path], &ServerConfig::default(), &[]);
+
+        // #insight the missing file is reported, the other files are checked.
+        assert_eq!(files.len(), 2);
+        assert!(files[0].diagnostics.iter().any(is_error));
+        assert!(files[0].diagnostics[0].message.contains("cannot check"));
+        assert!(!files[1].diagnostics.iter().any(is_error));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::{check::OutputFormat, transport::Transport};
+
+/// An LSP server for the Tan Language.
+#[derive(Debug, Parser)]
+#[command(name = "tan-language-server", version)]
+pub struct Cli {
+    /// Communicate over stdio (the default).
+    #[arg(long, conflicts_with_all = ["listen", "connect", "pipe"])]
+    pub stdio: bool,
+
+    /// Listen for a client on the given TCP port.
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["connect", "pipe"])]
+    pub listen: Option<u16>,
+
+    /// Connect to a client at the given TCP address, e.g. `127.0.0.1:9257`.
+    #[arg(long, value_name = "ADDR", conflicts_with = "pipe")]
+    pub connect: Option<String>,
+
+    /// Connect to a client at the given named pipe (Unix domain socket).
+    #[arg(long, value_name = "NAME")]
+    pub pipe: Option<String>,
+
+    /// Write the logs to the given file, instead of stderr.
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// The log level or filter directives, e.g. `debug` or
+    /// `tan_language_server=trace`. Overrides `RUST_LOG`.
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Record the JSON-RPC messages of the session to the given JSONL trace
+    /// file, e.g. to attach to a bug report.
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Reports the diagnostics of the given files or directories, exits with
+    /// failure if there are errors.
+    Check(CheckArgs),
+    /// Formats the given files or directories in place.
+    Format(FormatArgs),
+    /// Prints the document outline, as returned by `textDocument/documentSymbol`.
+    Symbols(SymbolsArgs),
+    /// Prints the definition of the symbol at a location, as returned by
+    /// `textDocument/definition`.
+    Definition(PositionArgs),
+    /// Prints the references of the symbol at a location, as returned by
+    /// `textDocument/references`.
+    References(ReferencesArgs),
+    /// Replays a recorded trace, and reports the responses that differ from
+    /// the recorded ones.
+    Replay(ReplayArgs),
+    /// Runs a sandbox job read from stdin, the worker process of the server,
+    /// see `sandbox::SANDBOX_WORKER_COMMAND`.
+    #[command(name = "sandbox-worker", hide = true)]
+    SandboxWorker,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// The files or directories to check, defaults to the current directory.
+    pub paths: Vec<PathBuf>,
+
+    /// The output format.
+    #[arg(long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct FormatArgs {
+    /// The files or directories to format, defaults to the current directory.
+    /// With `--stdin`, the path selects the dialect.
+    pub paths: Vec<PathBuf>,
+
+    /// Report the files that would change, with a unified diff, instead of
+    /// rewriting them. Exits with failure if any file would change.
+    #[arg(long)]
+    pub check: bool,
+
+    /// Format the standard input to the standard output.
+    #[arg(long)]
+    pub stdin: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// The JSONL trace, recorded with `--record`.
+    pub trace: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct SymbolsArgs {
+    pub file: PathBuf,
+
+    /// Print nested symbols, as returned to clients with hierarchical
+    /// document symbol support.
+    #[arg(long)]
+    pub hierarchical: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PositionArgs {
+    /// The location, `<file>:<line>:<col>`, lines and columns are 1-based.
+    pub location: String,
+}
+
+#[derive(Debug, Args)]
+pub struct ReferencesArgs {
+    #[command(flatten)]
+    pub position: PositionArgs,
+
+    /// Include the definition of the symbol.
+    #[arg(long)]
+    pub include_declaration: bool,
+}
+
+impl Cli {
+    pub fn transport(&self) -> Transport {
+        if let Some(port) = self.listen {
+            Transport::Listen(port)
+        } else if let Some(addr) = &self.connect {
+            Transport::Connect(addr.clone())
+        } else if let Some(name) = &self.pipe {
+            Transport::Pipe(name.clone())
+        } else {
+            Transport::Stdio
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use crate::{
+        check::OutputFormat,
+        cli::{Cli, Command},
+        transport::Transport,
+    };
+
+    #[test]
+    fn cli_transport_usage() {
+        let cli = Cli::parse_from(["tan-language-server"]);
+        assert_eq!(cli.transport(), Transport::Stdio);
+
+        let cli = Cli::parse_from(["tan-language-server", "--listen", "9257"]);
+        assert_eq!(cli.transport(), Transport::Listen(9257));
+
+        let cli = Cli::parse_from(["tan-language-server", "--pipe=/tmp/tan.sock"]);
+        assert_eq!(
+            cli.transport(),
+            Transport::Pipe(String::from("/tmp/tan.sock"))
+        );
+
+        assert!(
+            Cli::try_parse_from(["tan-language-server", "--stdio", "--listen", "9257"]).is_err()
+        );
+    }
+
+    #[test]
+    fn cli_check_usage() {
+        let cli = Cli::parse_from(["tan-language-server", "check", "src", "--format", "sarif"]);
+        let Some(Command::Check(args)) = cli.command else {
+            panic!("expected the check subcommand");
+        };
+        assert_eq!(args.paths, vec![PathBuf::from("src")]);
+        assert_eq!(args.format, OutputFormat::Sarif);
+    }
+}
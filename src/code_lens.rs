@@ -0,0 +1,156 @@
+use lsp_types::{CodeLens, Command, Location, Uri};
+use serde_json::json;
+use tan::expr::Expr;
+
+use crate::{
+    inlay_hints::func_params,
+    references::find_references,
+    util::{let_bindings, lsp_range_from_tan_range},
+};
+
+pub const RUN_COMMAND: &str = "tan.run";
+pub const TEST_COMMAND: &str = "tan.test";
+// #insight implemented by the editor extension.
+pub const SHOW_REFERENCES_COMMAND: &str = "tan.showReferences";
+
+pub fn is_test_document(uri: &str) -> bool {
+    uri.ends_with(".test.tan")
+}
+
+// #insight test functions are named `test-*`, e.g. `(let test-add (Func [] ...))`.
+pub fn is_test_func_name(name: &str) -> bool {
+    name.starts_with("test-")
+}
+
+/// Returns true if `name` is a test function, i.e. a `test-*` binding with a
+/// `Func` value, defined in the top-level of the document.
+pub fn is_test_func_defined(exprs: &[Expr], name: &str) -> bool {
+    is_test_func_name(name)
+        && exprs.iter().flat_map(let_bindings).any(|(binding, value)| {
+            binding.as_symbol() == Some(name) && func_params(value).is_some()
+        })
+}
+
+/// Computes the code lenses of a document. `sources` are all the workspace
+/// sources, used to count the references of the top-level bindings.
+pub fn compute_code_lenses(uri: &Uri, exprs: &[Expr], sources: &[(Uri, &[Expr])]) -> Vec<CodeLens> {
+    let mut lenses = Vec::new();
+
+    for expr in exprs {
+        for (name, value) in let_bindings(expr) {
+            let (Some(sym), Some(tan_range)) = (name.as_symbol(), name.range()) else {
+                continue;
+            };
+
+            let range = lsp_range_from_tan_range(tan_range);
+
+            let locations: Vec<Location> = sources
+                .iter()
+                .flat_map(|(source_uri, source_exprs)| {
+                    let definition_range = (source_uri == uri).then_some(range);
+                    find_references(source_exprs, sym, definition_range)
+                        .into_iter()
+                        .map(|range| Location::new(source_uri.clone(), range))
+                })
+                .collect();
+
+            let title = match locations.len() {
+                1 => String::from("1 reference"),
+                n => format!("{n} references"),
+            };
+
+            lenses.push(CodeLens {
+                range,
+                command: Some(Command {
+                    title,
+                    command: SHOW_REFERENCES_COMMAND.to_string(),
+                    arguments: Some(vec![json!(uri), json!(range.start), json!(locations)]),
+                }),
+                data: None,
+            });
+
+            if func_params(value).is_none() {
+                continue;
+            }
+
+            if sym == "main" {
+                lenses.push(CodeLens {
+                    range,
+                    command: Some(Command {
+                        title: String::from("▶ Run"),
+                        command: RUN_COMMAND.to_string(),
+                        arguments: Some(vec![json!(uri)]),
+                    }),
+                    data: None,
+                });
+            } else if is_test_document(uri.as_str()) && is_test_func_name(sym) {
+                lenses.push(CodeLens {
+                    range,
+                    command: Some(Command {
+                        title: String::from("▶ Test"),
+                        command: TEST_COMMAND.to_string(),
+                        arguments: Some(vec![json!(uri), json!(sym)]),
+                    }),
+                    data: None,
+                });
+            }
+        }
+    }
+
+    lenses
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use lsp_types::Uri;
+
+    use crate::{
+        code_lens::{compute_code_lenses, is_test_func_defined},
+        util::parse_string_all,
+    };
+
+    fn titles(uri: &str, input: &str) -> Vec<String> {
+        let uri = Uri::from_str(uri).unwrap();
+        let exprs = parse_string_all(input).unwrap();
+        let sources = vec![(uri.clone(), exprs.as_slice())];
+        compute_code_lenses(&uri, &exprs, &sources)
+            .into_iter()
+            .filter_map(|lens| lens.command.map(|command| command.title))
+            .collect()
+    }
+
+    #[test]
+    fn compute_code_lenses_usage() {
+        let input = r#"
+        (let a 1)
+        (let main (Func [] (writeln a)))
+        (let test-a (Func [] (assert-eq a 1)))
+        "#;
+
+        let titles_main = titles("file:///project/main.tan", input);
+        assert_eq!(
+            titles_main,
+            vec!["2 references", "0 references", "▶ Run", "0 references"]
+        );
+
+        let titles_test = titles("file:///project/main.test.tan", input);
+        assert!(titles_test.contains(&String::from("▶ Test")));
+    }
+
+    #[test]
+    fn is_test_func_defined_usage() {
+        let input =
+            "(let test-a (Func [] (assert-eq 1 1)))\n(let test-b 1)\n(let helper (Func [] 1))\n";
+        let exprs = parse_string_all(input).unwrap();
+
+        assert!(is_test_func_defined(&exprs, "test-a"));
+        assert!(!is_test_func_defined(&exprs, "test-b"));
+        assert!(!is_test_func_defined(&exprs, "helper"));
+        assert!(!is_test_func_defined(
+            &exprs,
+            "test-a) (fs/remove-dir \"/\""
+        ));
+    }
+}
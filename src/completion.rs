@@ -0,0 +1,89 @@
+use lsp_types::{CompletionItem, CompletionItemKind, InsertTextFormat};
+use tan::expr::Expr;
+
+use crate::{inlay_hints::func_params, util::let_bindings};
+
+// #insight
+// Completes the top-level `let` bindings of the document. Functions are
+// completed with their parameters as placeholders, e.g. `greet ${1:name}`, if
+// the client supports snippets.
+// #todo also complete the imported bindings.
+
+/// Returns the snippet that completes a call, e.g. `greet ${1:name}`.
+fn call_snippet(name: &str, params: &[String]) -> String {
+    let mut snippet = name.to_string();
+
+    for (index, param) in params.iter().enumerate() {
+        snippet.push_str(&format!(" ${{{}:{param}}}", index + 1));
+    }
+
+    snippet
+}
+
+pub fn compute_completions(exprs: &[Expr], snippets: bool) -> Vec<CompletionItem> {
+    exprs
+        .iter()
+        .flat_map(let_bindings)
+        .filter_map(|(name, value)| {
+            let name = name.as_symbol()?;
+
+            let Some(params) = func_params(value) else {
+                return Some(CompletionItem {
+                    label: name.to_string(),
+                    kind: Some(CompletionItemKind::VARIABLE),
+                    ..Default::default()
+                });
+            };
+
+            let (insert_text, insert_text_format) = if snippets && !params.is_empty() {
+                (
+                    Some(call_snippet(name, &params)),
+                    Some(InsertTextFormat::SNIPPET),
+                )
+            } else {
+                (None, None)
+            };
+
+            Some(CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some(format!("(Func [{}])", params.join(" "))),
+                insert_text,
+                insert_text_format,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{CompletionItemKind, InsertTextFormat};
+
+    use crate::{completion::compute_completions, util::parse_string_all};
+
+    #[test]
+    fn compute_completions_usage() {
+        let input = r#"
+        (let greeting "Hello")
+        (let greet (Func [name punctuation] (+ greeting name punctuation)))
+        "#;
+
+        let exprs = parse_string_all(input).unwrap();
+
+        let items = compute_completions(&exprs, true);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].kind, Some(CompletionItemKind::VARIABLE));
+        assert!(items[0].insert_text.is_none());
+        assert_eq!(
+            items[1].insert_text.as_deref(),
+            Some("greet ${1:name} ${2:punctuation}")
+        );
+        assert_eq!(items[1].insert_text_format, Some(InsertTextFormat::SNIPPET));
+
+        // #insight without snippet support, the label is inserted.
+        let items = compute_completions(&exprs, false);
+        assert!(items[1].insert_text.is_none());
+        assert!(items[1].insert_text_format.is_none());
+    }
+}
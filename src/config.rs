@@ -0,0 +1,385 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+use tan_formatting::types::Dialect;
+
+use crate::{inlay_hints::InlayHintsConfig, util::dialect_from_document_uri};
+
+// #insight
+// The configuration is read from `initializationOptions`, pulled with
+// `workspace/configuration` (section `tan`) and updated on
+// `workspace/didChangeConfiguration`. All fields are optional, invalid fields
+// fall back to their defaults. The project configuration file
+// (`tan.config.tan`) overrides the editor settings.
+
+pub const CONFIGURATION_SECTION: &str = "tan";
+
+/// Returns the `tan` section of the settings, or the settings themselves.
+pub fn settings_section(value: &serde_json::Value) -> &serde_json::Value {
+    value.get(CONFIGURATION_SECTION).unwrap_or(value)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServerConfig {
+    pub lints: LintsConfig,
+    pub formatter: FormatterConfig,
+    pub inlay_hints: InlayHintsConfig,
+    pub eval: EvalConfig,
+    pub workspace: WorkspaceConfig,
+    pub modules: ModulesConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleSeverity {
+    Off,
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LintsConfig {
+    pub enabled: bool,
+    /// Severity overrides, keyed by diagnostic code.
+    pub rules: HashMap<String, RuleSeverity>,
+}
+
+impl Default for LintsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rules: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DialectName {
+    Code,
+    Data,
+}
+
+impl From<DialectName> for Dialect {
+    fn from(name: DialectName) -> Self {
+        match name {
+            DialectName::Code => Dialect::Code,
+            DialectName::Data => Dialect::Data,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatterConfig {
+    /// Dialect overrides, keyed by glob, e.g. `{ "**/*.style.tan": "data" }`.
+    pub dialects: HashMap<String, DialectName>,
+    // #todo add `line_width`, once tan-formatting supports a configurable line width.
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EvalConfig {
+    /// Remove the (known) filesystem, network and process foreign functions,
+    /// by name. A guard against accidental IO, not a security sandbox.
+    pub sandbox: bool,
+    /// The time limit of explicit evaluations, e.g. `tan.run`.
+    pub timeout_ms: u64,
+    /// The time limit of analysis-time evaluation.
+    pub analysis_timeout_ms: u64,
+}
+
+impl Default for EvalConfig {
+    fn default() -> Self {
+        Self {
+            sandbox: true,
+            timeout_ms: 10_000,
+            analysis_timeout_ms: 2_000,
+        }
+    }
+}
+
+impl EvalConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub fn analysis_timeout(&self) -> Duration {
+        Duration::from_millis(self.analysis_timeout_ms)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WorkspaceConfig {
+    /// Globs of paths to exclude from workspace scans, e.g. `**/vendor/**`.
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ModulesConfig {
+    /// Additional directories to resolve relative `use` paths against,
+    /// relative to the workspace root.
+    pub search_paths: Vec<String>,
+}
+
+impl WorkspaceConfig {
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.exclude.iter().any(|pattern| glob_match(pattern, path))
+    }
+}
+
+/// Deserializes a section of the configuration, the invalid fields fall back
+/// to their defaults and are reported in `errors`.
+fn section_from_value<T: DeserializeOwned + Default>(
+    name: &str,
+    value: Option<&Value>,
+    errors: &mut Vec<String>,
+) -> T {
+    let Some(value) = value else {
+        return T::default();
+    };
+
+    let error = match serde_json::from_value(value.clone()) {
+        Ok(section) => return section,
+        Err(error) => error,
+    };
+
+    let Some(fields) = value.as_object() else {
+        errors.push(format!("`{name}`: {error}"));
+        return T::default();
+    };
+
+    // #insight keep the fields that deserialize, one by one.
+    let mut valid = Map::new();
+
+    for (key, field) in fields {
+        let mut candidate = valid.clone();
+        candidate.insert(key.clone(), field.clone());
+
+        match serde_json::from_value::<T>(Value::Object(candidate)) {
+            Ok(_) => {
+                valid.insert(key.clone(), field.clone());
+            }
+            Err(error) => errors.push(format!("`{name}.{key}`: {error}")),
+        }
+    }
+
+    serde_json::from_value(Value::Object(valid)).unwrap_or_default()
+}
+
+impl ServerConfig {
+    /// Deserializes the configuration, the value is either the configuration
+    /// itself or an object with a `tan` section. Invalid fields fall back to
+    /// their defaults, the errors are returned alongside the configuration.
+    pub fn from_value(value: &Value) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+
+        let empty = Map::new();
+        let sections = match settings_section(value) {
+            Value::Null => &empty,
+            Value::Object(sections) => sections,
+            _ => {
+                errors.push(String::from("the configuration should be an object"));
+                &empty
+            }
+        };
+
+        let config = Self {
+            lints: section_from_value("lints", sections.get("lints"), &mut errors),
+            formatter: section_from_value("formatter", sections.get("formatter"), &mut errors),
+            inlay_hints: section_from_value("inlayHints", sections.get("inlayHints"), &mut errors),
+            eval: section_from_value("eval", sections.get("eval"), &mut errors),
+            workspace: section_from_value("workspace", sections.get("workspace"), &mut errors),
+            modules: section_from_value("modules", sections.get("modules"), &mut errors),
+        };
+
+        (config, errors)
+    }
+
+    /// The module search paths, resolved against the workspace root.
+    pub fn module_search_paths(&self, root: Option<&Path>) -> Vec<PathBuf> {
+        let Some(root) = root else {
+            return Vec::new();
+        };
+
+        self.modules
+            .search_paths
+            .iter()
+            .map(|path| root.join(path))
+            .collect()
+    }
+
+    pub fn dialect_for(&self, uri: &str) -> Dialect {
+        for (pattern, dialect) in &self.formatter.dialects {
+            if glob_match(pattern, uri) {
+                return (*dialect).into();
+            }
+        }
+
+        dialect_from_document_uri(uri)
+    }
+
+    /// Applies the lint severity overrides, removes the diagnostics of disabled
+    /// rules.
+    pub fn apply_lint_rules(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        diagnostics
+            .into_iter()
+            .filter_map(|mut diagnostic| {
+                let code = match &diagnostic.code {
+                    Some(NumberOrString::String(code)) => code.clone(),
+                    Some(NumberOrString::Number(code)) => code.to_string(),
+                    None => return Some(diagnostic),
+                };
+
+                diagnostic.severity = match self.lints.rules.get(&code) {
+                    None => diagnostic.severity,
+                    Some(RuleSeverity::Off) => return None,
+                    Some(RuleSeverity::Error) => Some(DiagnosticSeverity::ERROR),
+                    Some(RuleSeverity::Warning) => Some(DiagnosticSeverity::WARNING),
+                    Some(RuleSeverity::Information) => Some(DiagnosticSeverity::INFORMATION),
+                    Some(RuleSeverity::Hint) => Some(DiagnosticSeverity::HINT),
+                };
+
+                Some(diagnostic)
+            })
+            .collect()
+    }
+}
+
+/// Matches a path against a glob pattern. Supports `*` (within a path
+/// segment), `**` (across segments) and `?`. Patterns without a `/` match the
+/// file name, e.g. `*.data.tan`.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    if !pattern.contains('/') {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        return glob_match_bytes(pattern.as_bytes(), name.as_bytes());
+    }
+
+    // #insight patterns are matched against the path suffix, e.g. `src/**`.
+    let pattern = pattern.trim_start_matches("./");
+    let pattern = if pattern.starts_with('/') || pattern.starts_with("**") {
+        pattern.to_string()
+    } else {
+        format!("**/{pattern}")
+    };
+
+    glob_match_bytes(pattern.as_bytes(), path.as_bytes())
+}
+
+fn glob_match_bytes(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob_match_bytes(rest, path)
+                || (0..path.len())
+                    .any(|i| path[i] == b'/' && glob_match_bytes(rest, &path[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_match_bytes(rest, &path[i..])),
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != b'/')
+            .any(|i| glob_match_bytes(rest, &path[i..])),
+        [b'?', rest @ ..] => {
+            !path.is_empty() && path[0] != b'/' && glob_match_bytes(rest, &path[1..])
+        }
+        [c, rest @ ..] => !path.is_empty() && path[0] == *c && glob_match_bytes(rest, &path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+    use serde_json::json;
+
+    use crate::config::{glob_match, RuleSeverity, ServerConfig};
+
+    #[test]
+    fn glob_match_usage() {
+        assert!(glob_match("*.data.tan", "file:///project/users.data.tan"));
+        assert!(!glob_match("*.data.tan", "file:///project/main.tan"));
+        assert!(glob_match("**/vendor/**", "/project/vendor/lib/main.tan"));
+        assert!(glob_match("styles/*.tan", "/project/styles/main.tan"));
+        assert!(!glob_match(
+            "styles/*.tan",
+            "/project/styles/nested/main.tan"
+        ));
+        assert!(glob_match("ma?n.tan", "/project/main.tan"));
+    }
+
+    #[test]
+    fn server_config_from_value() {
+        let value = json!({
+            "tan": {
+                "lints": { "rules": { "snake-case": "off", "unused": "hint" } },
+                "inlayHints": { "types": false },
+                "eval": { "sandbox": false },
+                "workspace": { "exclude": ["**/vendor/**"] }
+            }
+        });
+
+        let (config, errors) = ServerConfig::from_value(&value);
+        assert!(errors.is_empty());
+        assert!(config.lints.enabled);
+        assert!(config.inlay_hints.parameter_names);
+        assert!(!config.inlay_hints.types);
+        assert!(!config.eval.sandbox);
+        assert_eq!(config.eval.timeout_ms, 10_000);
+        assert!(config.workspace.is_excluded("/project/vendor/x.tan"));
+
+        let diagnostic = |code: &str| Diagnostic {
+            code: Some(NumberOrString::String(code.to_string())),
+            severity: Some(DiagnosticSeverity::WARNING),
+            ..Default::default()
+        };
+
+        let diagnostics = config.apply_lint_rules(vec![
+            diagnostic("snake-case"),
+            diagnostic("unused"),
+            diagnostic("other"),
+        ]);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::HINT));
+        assert_eq!(diagnostics[1].severity, Some(DiagnosticSeverity::WARNING));
+
+        let (_, errors) = ServerConfig::from_value(&serde_json::Value::Null);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn server_config_from_value_falls_back_per_field() {
+        let value = json!({
+            "lints": { "enabled": "yes", "rules": { "unused": "hint" } },
+            "eval": { "timeoutMs": 500, "sandbox": 1 },
+            "workspace": 42
+        });
+
+        let (config, errors) = ServerConfig::from_value(&value);
+
+        // #insight the valid fields of a section are kept.
+        assert!(config.lints.enabled);
+        assert_eq!(config.lints.rules.get("unused"), Some(&RuleSeverity::Hint));
+        assert_eq!(config.eval.timeout_ms, 500);
+        assert!(config.eval.sandbox);
+        assert!(config.workspace.exclude.is_empty());
+
+        assert_eq!(errors.len(), 3);
+        assert!(errors
+            .iter()
+            .any(|error| error.starts_with("`lints.enabled`")));
+        assert!(errors
+            .iter()
+            .any(|error| error.starts_with("`eval.sandbox`")));
+        assert!(errors.iter().any(|error| error.starts_with("`workspace`")));
+    }
+}
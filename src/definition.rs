@@ -0,0 +1,161 @@
+use lsp_types::{GotoDefinitionResponse, Location, Position, Uri};
+use tan::expr::Expr;
+
+use crate::{
+    imports::IndexedBinding,
+    references::symbol_at_position,
+    util::{let_bindings, lsp_range_from_tan_range, uri_from_path},
+};
+
+// #insight
+// Definitions are the top-level `let` bindings of the workspace sources,
+// resolved by name. Definitions in the current document take precedence.
+// Qualified names, e.g. `math/add`, resolve to the bindings of the imported
+// modules, that may live outside the workspace, e.g. in the standard library.
+
+/// Returns the locations of the top-level definitions of the symbol `name`.
+pub fn find_definitions(uri: &Uri, sources: &[(Uri, &[Expr])], name: &str) -> Vec<Location> {
+    let mut locations: Vec<Location> = Vec::new();
+
+    for (source_uri, exprs) in sources {
+        for expr in exprs.iter() {
+            for (binding_name, _) in let_bindings(expr) {
+                if binding_name.as_symbol() != Some(name) {
+                    continue;
+                }
+
+                if let Some(range) = binding_name.range() {
+                    locations.push(Location::new(
+                        source_uri.clone(),
+                        lsp_range_from_tan_range(range),
+                    ));
+                }
+            }
+        }
+    }
+
+    if locations.iter().any(|location| &location.uri == uri) {
+        locations.retain(|location| &location.uri == uri);
+    }
+
+    locations
+}
+
+/// Returns the locations of the imported bindings named `name`.
+pub fn find_import_definitions(imports: &[IndexedBinding], name: &str) -> Vec<Location> {
+    imports
+        .iter()
+        .filter(|binding| binding.name == name)
+        .filter_map(|binding| {
+            Some(Location::new(
+                uri_from_path(&binding.path)?,
+                lsp_range_from_tan_range(binding.range?),
+            ))
+        })
+        .collect()
+}
+
+/// Computes the definition of the symbol at the position, `imports` are the
+/// bindings imported by the document.
+pub fn compute_definition(
+    uri: &Uri,
+    exprs: &[Expr],
+    position: Position,
+    sources: &[(Uri, &[Expr])],
+    imports: &[IndexedBinding],
+) -> Option<GotoDefinitionResponse> {
+    let (name, _) = symbol_at_position(exprs, position)?;
+
+    let mut locations = find_definitions(uri, sources, &name);
+
+    if locations.is_empty() {
+        locations = find_import_definitions(imports, &name);
+    }
+
+    if locations.is_empty() {
+        return None;
+    }
+
+    Some(GotoDefinitionResponse::Array(locations))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use lsp_types::{GotoDefinitionResponse, Position, Uri};
+
+    use crate::{
+        definition::compute_definition,
+        imports::imported_bindings,
+        util::{parse_string_all, uri_from_path},
+    };
+
+    #[test]
+    fn compute_definition_usage() {
+        let main_uri = Uri::from_str("file:///project/main.tan").unwrap();
+        let main_input = "(let a 1)\n(let b (+ a zonk))\n";
+        let main_exprs = parse_string_all(main_input).unwrap();
+
+        let lib_uri = Uri::from_str("file:///project/lib.tan").unwrap();
+        let lib_exprs = parse_string_all("(let zonk 2)\n(let a 3)\n").unwrap();
+
+        let sources = vec![
+            (main_uri.clone(), main_exprs.as_slice()),
+            (lib_uri.clone(), lib_exprs.as_slice()),
+        ];
+
+        // #insight the cursor is on `a` in `(+ a zonk)`.
+        let Some(GotoDefinitionResponse::Array(locations)) =
+            compute_definition(&main_uri, &main_exprs, Position::new(1, 10), &sources, &[])
+        else {
+            panic!("expected a definition");
+        };
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].uri, main_uri);
+        assert_eq!(locations[0].range.start, Position::new(0, 5));
+
+        // #insight the cursor is on `zonk`.
+        let Some(GotoDefinitionResponse::Array(locations)) =
+            compute_definition(&main_uri, &main_exprs, Position::new(1, 13), &sources, &[])
+        else {
+            panic!("expected a definition");
+        };
+        assert_eq!(locations[0].uri, lib_uri);
+
+        assert!(
+            compute_definition(&main_uri, &main_exprs, Position::new(1, 1), &sources, &[])
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn compute_definition_resolves_imports() {
+        let dir = std::env::temp_dir().join(format!(
+            "tan-language-server-import-definitions-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("math.tan"),
+            "(let pi 3.14)\n(let add (Func [x y] (+ x y)))\n",
+        )
+        .unwrap();
+
+        let uri = uri_from_path(&dir.join("main.tan")).unwrap();
+        let exprs = parse_string_all("(use ./math)\n(let a (math/add 1 2))\n").unwrap();
+        let imports = imported_bindings(&exprs, &dir, &[]);
+
+        // #insight the cursor is on `math/add`.
+        let Some(GotoDefinitionResponse::Array(locations)) =
+            compute_definition(&uri, &exprs, Position::new(1, 9), &[], &imports)
+        else {
+            panic!("expected a definition");
+        };
+        assert_eq!(locations.len(), 1);
+        assert!(locations[0].uri.as_str().ends_with("math.tan"));
+        assert_eq!(locations[0].range.start, Position::new(1, 5));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use lsp_types::{Diagnostic, DiagnosticSeverity};
+use tan::{error::Error, expr::Expr};
+use tan_lints::compute_diagnostics;
+
+use crate::{
+    analysis::{analyze_module, ModuleAnalysis},
+    config::{EvalConfig, ServerConfig},
+    eval::{Cancellation, EvalTimeout},
+    imports::compute_import_diagnostics,
+    util::{document_module_path, lsp_range_top},
+};
+
+// #insight
+// The diagnostics are shared by the LSP server and the `check` subcommand, CI
+// reports exactly the diagnostics the editor shows. The server computes the
+// analysis diagnostics in the background, see `AnalysisQueue`.
+
+/// Computes the lint and import diagnostics of a document, i.e. the
+/// diagnostics that do not require evaluation.
+pub fn compute_static_diagnostics(
+    uri: &str,
+    parse_result: &Result<Vec<Expr>, Vec<Error>>,
+    config: &ServerConfig,
+    search_paths: &[PathBuf],
+) -> Result<Vec<Diagnostic>, std::io::Error> {
+    // #insight parse errors are always reported, lints only if enabled.
+    let mut diagnostics = if parse_result.is_err() || config.lints.enabled {
+        config.apply_lint_rules(compute_diagnostics(parse_result))
+    } else {
+        Vec::new()
+    };
+
+    let Ok(exprs) = parse_result else {
+        return Ok(diagnostics);
+    };
+
+    let module_path = document_module_path(uri)?;
+    diagnostics.extend(compute_import_diagnostics(
+        exprs,
+        &module_path,
+        search_paths,
+    ));
+
+    Ok(diagnostics)
+}
+
+/// The diagnostics of an analysis, a timeout is reported as a warning.
+pub fn analysis_diagnostics(
+    analysis: Result<ModuleAnalysis, EvalTimeout>,
+    config: &EvalConfig,
+) -> Vec<Diagnostic> {
+    match analysis {
+        Ok(analysis) => analysis.diagnostics,
+        Err(_) => vec![Diagnostic {
+            range: lsp_range_top(),
+            severity: Some(DiagnosticSeverity::WARNING),
+            source: Some(String::from("tan")),
+            message: format!("analysis timed out after {:?}", config.analysis_timeout()),
+            ..Default::default()
+        }],
+    }
+}
+
+/// Computes the lint, import and analysis diagnostics of a document.
+pub fn compute_document_diagnostics(
+    uri: &str,
+    input: &str,
+    parse_result: &Result<Vec<Expr>, Vec<Error>>,
+    config: &ServerConfig,
+    search_paths: Vec<PathBuf>,
+) -> Result<Vec<Diagnostic>, std::io::Error> {
+    let mut diagnostics = compute_static_diagnostics(uri, parse_result, config, &search_paths)?;
+
+    if parse_result.is_ok() {
+        let analysis = analyze_module(
+            input.to_string(),
+            uri.to_string(),
+            search_paths,
+            &config.eval,
+            &Cancellation::default(),
+        );
+        diagnostics.extend(analysis_diagnostics(analysis, &config.eval));
+    }
+
+    Ok(diagnostics)
+}
+
+pub fn is_error(diagnostic: &Diagnostic) -> bool {
+    // #insight clients (e.g. VS Code) treat diagnostics without severity as errors.
+    matches!(diagnostic.severity, None | Some(DiagnosticSeverity::ERROR))
+}
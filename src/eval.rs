@@ -0,0 +1,355 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use crossbeam::channel::Sender;
+use lsp_types::Range;
+use serde::Serialize;
+use tan::{
+    api::{compile, eval_string},
+    context::Context,
+    error::Error,
+    eval::eval,
+    expr::{expr_clone, Expr},
+};
+
+use crate::{
+    config::EvalConfig,
+    sandbox::{run_cancellable, SandboxJob},
+    util::{
+        is_position_in_range, let_bindings, lsp_range_from_tan_range, make_analysis_context,
+        parse_string_all,
+    },
+};
+
+// #insight
+// Evaluation happens in a worker process, so that an infinite loop (or a stack
+// overflow) does not hang or crash the server. The worker is killed on timeout,
+// see `sandbox`.
+
+pub const EVALUATE_SELECTION_COMMAND: &str = "tan.evaluateSelection";
+
+// #insight segments of foreign function names that perform filesystem, network
+// or process IO, e.g. `fs/read-file`, `process/spawn`.
+// #insight the name filter is a best-effort guard against accidental IO in
+// analysis, _not_ a security sandbox: only the top-scope bindings are filtered,
+// by name. Never evaluate untrusted input on the assumption that it is safe.
+const DENIED_NAME_SEGMENTS: [&str; 13] = [
+    "fs", "file", "dir", "os", "process", "shell", "exec", "spawn", "exit", "http", "net",
+    "socket", "env",
+];
+
+#[derive(Debug)]
+pub struct EvalTimeout;
+
+/// A flag to cancel an evaluation, the worker process is killed once it is
+/// set.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The result of the `tan.evaluateSelection` command.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationResult {
+    /// The range of the evaluated form.
+    pub range: Range,
+    /// The printed value of the evaluated form.
+    pub value: Option<String>,
+    pub errors: Vec<String>,
+    pub output: Vec<String>,
+}
+
+pub fn is_denied_name(name: &str) -> bool {
+    name.split(['/', '-'])
+        .any(|segment| DENIED_NAME_SEGMENTS.contains(&segment))
+}
+
+fn output_func(sender: Sender<String>, newline: bool) -> Expr {
+    // #insight buffer partial lines written with `write`, emit on newline.
+    let buffer = Arc::new(Mutex::new(String::new()));
+
+    Expr::ForeignFunc(Arc::new(
+        move |args: &[Expr], _context: &mut Context| -> Result<Expr, Error> {
+            let mut buffer = buffer.lock().expect("not poisoned");
+
+            for arg in args {
+                if let Some(s) = arg.as_string() {
+                    buffer.push_str(s);
+                } else {
+                    buffer.push_str(&arg.to_string());
+                }
+            }
+
+            if newline {
+                buffer.push('\n');
+            }
+
+            while let Some(index) = buffer.find('\n') {
+                let line: String = buffer.drain(..=index).collect();
+                let _ = sender.send(line.trim_end().to_string());
+            }
+
+            Ok(Expr::None)
+        },
+    ))
+}
+
+/// Creates a context without the (known) filesystem, network and process
+/// foreign functions, unless `sandbox` is false. This is not a security
+/// boundary, see `DENIED_NAME_SEGMENTS`. Output of `write`/`writeln` is
+/// redirected to the sender, instead of stdout (that is used by the LSP
+/// transport).
+pub fn make_sandboxed_context(
+    document_uri: &str,
+    sender: Sender<String>,
+    sandbox: bool,
+) -> Result<Context, std::io::Error> {
+    let context = make_analysis_context(document_uri)?;
+
+    context
+        .top_scope
+        .insert("write", output_func(sender.clone(), false));
+    context
+        .top_scope
+        .insert("writeln", output_func(sender, true));
+
+    if sandbox {
+        context
+            .top_scope
+            .bindings
+            .write()
+            .expect("not poisoned")
+            .retain(|name, _| !is_denied_name(name));
+    }
+
+    Ok(context)
+}
+
+/// Evaluates the input, or the forms covered by the selection, in a
+/// sandboxed context. Runs in the worker process, see `SandboxJob`.
+pub fn eval_sync(
+    input: &str,
+    document_uri: &str,
+    selection: Option<Range>,
+    sandbox: bool,
+    output: Sender<String>,
+) -> Result<String, Vec<String>> {
+    let mut context = make_sandboxed_context(document_uri, output, sandbox)
+        .map_err(|error| vec![error.to_string()])?;
+
+    let to_strings = |errors: Vec<Error>| -> Vec<String> {
+        errors.iter().map(|error| error.to_string()).collect()
+    };
+
+    let Some(selection) = selection else {
+        return eval_string(input, &mut context)
+            .map(|value| value.to_string())
+            .map_err(to_strings);
+    };
+
+    let exprs = parse_string_all(input).map_err(to_strings)?;
+    let Some((selected, _)) = selection_exprs(&exprs, selection) else {
+        return Err(vec![String::from("no expression at the selection")]);
+    };
+
+    let mut value = Expr::None;
+    for expr in selected {
+        let expr = compile(expr, &mut context).map_err(to_strings)?;
+        value = eval(&expr, &mut context).map_err(|error| to_strings(vec![error]))?;
+    }
+    Ok(value.to_string())
+}
+
+fn eval_job(
+    job: SandboxJob,
+    config: &EvalConfig,
+    on_output: impl FnMut(String),
+) -> Result<Result<String, Vec<String>>, EvalTimeout> {
+    let result = run_cancellable(&job, config.timeout(), &Cancellation::default(), on_output)?;
+    Ok(result.unwrap_or_else(|| Err(vec![String::from("the evaluation process failed")])))
+}
+
+/// Evaluates the input in a sandboxed context, `on_output` is called for every
+/// line of output while the evaluation is running.
+pub fn eval_sandboxed(
+    input: String,
+    document_uri: String,
+    config: &EvalConfig,
+    on_output: impl FnMut(String),
+) -> Result<Result<String, Vec<String>>, EvalTimeout> {
+    let job = SandboxJob::Eval {
+        input,
+        document_uri,
+        selection: None,
+        sandbox: config.sandbox,
+    };
+    eval_job(job, config, on_output)
+}
+
+/// Evaluates the top-level forms of the input covered by the selection, see
+/// `selection_exprs`, returns the value of the last form.
+pub fn eval_selection_sandboxed(
+    input: String,
+    document_uri: String,
+    selection: Range,
+    config: &EvalConfig,
+    on_output: impl FnMut(String),
+) -> Result<Result<String, Vec<String>>, EvalTimeout> {
+    let job = SandboxJob::Eval {
+        input,
+        document_uri,
+        selection: Some(selection),
+        sandbox: config.sandbox,
+    };
+    eval_job(job, config, on_output)
+}
+
+/// Returns the indices of the first and last top-level forms covered by the
+/// selection (or the form at the cursor, if the selection is empty), and
+/// their range.
+pub fn select_forms(exprs: &[Expr], selection: Range) -> Option<(usize, usize, Range)> {
+    let selected: Vec<(usize, Range)> = exprs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, expr)| {
+            let range = lsp_range_from_tan_range(expr.range()?);
+            let is_covered = if selection.start == selection.end {
+                is_position_in_range(selection.start, range)
+            } else {
+                range.start < selection.end && selection.start < range.end
+            };
+            is_covered.then_some((index, range))
+        })
+        .collect();
+
+    let (first, first_range) = *selected.first()?;
+    let (last, last_range) = *selected.last()?;
+
+    Some((first, last, Range::new(first_range.start, last_range.end)))
+}
+
+/// Returns the expressions to evaluate the top-level forms covered by the
+/// selection (or the form at the cursor, if the selection is empty), and their
+/// range. The expressions are seeded with the preceding `let` definitions and
+/// `use` imports.
+pub fn selection_exprs(exprs: &[Expr], selection: Range) -> Option<(Vec<Expr>, Range)> {
+    let (first, last, range) = select_forms(exprs, selection)?;
+
+    let mut seed: Vec<Expr> = exprs[..first]
+        .iter()
+        .filter(|expr| {
+            let is_use = expr
+                .as_list()
+                .and_then(|terms| terms.first())
+                .and_then(|op| op.as_symbol())
+                == Some("use");
+            is_use || !let_bindings(expr).is_empty()
+        })
+        .map(expr_clone)
+        .collect();
+
+    seed.extend(exprs[first..=last].iter().map(expr_clone));
+
+    Some((seed, range))
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, Range};
+
+    use crate::{
+        config::EvalConfig,
+        eval::{eval_sandboxed, eval_selection_sandboxed, is_denied_name, selection_exprs},
+        util::parse_string_all,
+    };
+
+    #[test]
+    fn is_denied_name_usage() {
+        assert!(is_denied_name("fs/read-file"));
+        assert!(is_denied_name("process/spawn"));
+        assert!(is_denied_name("read-file"));
+        assert!(!is_denied_name("writeln"));
+        assert!(!is_denied_name("+"));
+    }
+
+    #[test]
+    fn eval_sandboxed_usage() {
+        let mut output = Vec::new();
+        let result = eval_sandboxed(
+            String::from("(writeln \"hello\")\n(+ 1 2)"),
+            String::from("file:///project/main.tan"),
+            &EvalConfig::default(),
+            |line| output.push(line),
+        );
+        assert_eq!(result.unwrap(), Ok(String::from("3")));
+        assert_eq!(output, vec![String::from("hello")]);
+    }
+
+    #[test]
+    fn runaway_evaluation_times_out() {
+        let config = EvalConfig {
+            timeout_ms: 500,
+            ..EvalConfig::default()
+        };
+        // #insight pure recursion, no foreign function is called.
+        let result = eval_sandboxed(
+            String::from("(let f (Func [] (f)))\n(f)"),
+            String::from("file:///project/main.tan"),
+            &config,
+            |_| {},
+        );
+        assert!(!matches!(result, Ok(Ok(_))));
+    }
+
+    #[test]
+    fn selection_exprs_usage() {
+        let input =
+            "(let a 1)\n(writeln \"side-effect\")\n(let b 2)\n(+ a b)\n(let c 3)\n(* b c)\n";
+        let exprs = parse_string_all(input).unwrap();
+
+        // #insight an empty selection selects the form at the cursor.
+        let cursor = Range::new(Position::new(3, 2), Position::new(3, 2));
+        let (selected, range) = selection_exprs(&exprs, cursor).unwrap();
+        assert_eq!(range, Range::new(Position::new(3, 0), Position::new(3, 7)));
+        // #insight the `let` seeds and the selected form, without the side-effect.
+        assert_eq!(selected.len(), 3);
+
+        let result = eval_selection_sandboxed(
+            input.to_string(),
+            String::from("file:///project/main.tan"),
+            cursor,
+            &EvalConfig::default(),
+            |_| {},
+        );
+        assert_eq!(result.unwrap(), Ok(String::from("3")));
+
+        // #insight the selection covers `(let c 3)` and `(* b c)`, the value is the last one.
+        let selection = Range::new(Position::new(4, 3), Position::new(5, 2));
+        let (_, range) = selection_exprs(&exprs, selection).unwrap();
+        assert_eq!(range.start, Position::new(4, 0));
+        assert_eq!(range.end.line, 5);
+
+        let result = eval_selection_sandboxed(
+            input.to_string(),
+            String::from("file:///project/main.tan"),
+            selection,
+            &EvalConfig::default(),
+            |_| {},
+        );
+        assert_eq!(result.unwrap(), Ok(String::from("6")));
+
+        let nothing = Range::new(Position::new(10, 0), Position::new(10, 0));
+        assert!(selection_exprs(&exprs, nothing).is_none());
+    }
+}
@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+
+use crossbeam::channel::unbounded;
+use lsp_types::{request::Request, Position, Range, TextDocumentPositionParams};
+use serde::{Deserialize, Serialize};
+use tan::{
+    api::compile,
+    expr::{expr_clone, Expr},
+};
+use tan_formatting::{pretty::Formatter, types::Dialect};
+use tracing::trace;
+
+use crate::{
+    config::EvalConfig,
+    eval::{make_sandboxed_context, Cancellation, EvalTimeout},
+    sandbox::{run_cancellable, SandboxJob},
+    util::{is_position_in_range, lsp_range_from_tan_range, parse_module_file, parse_string_all},
+};
+
+// #insight
+// The macros are defined by the top-level `let` forms of the module, the
+// forms before the expanded form are analyzed (in the sandboxed context)
+// before the expansion.
+
+#[derive(Debug)]
+pub enum ExpandMacro {}
+
+impl Request for ExpandMacro {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<MacroExpansion>;
+    const METHOD: &'static str = "tan/expandMacro";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MacroExpansion {
+    /// The range of the expanded expression.
+    pub range: Range,
+    /// The pretty-printed expansion, if the expression compiles.
+    pub expansion: Option<String>,
+    pub errors: Vec<String>,
+}
+
+/// Returns the innermost list expression that contains the position.
+pub fn enclosing_list(exprs: &[Expr], position: Position) -> Option<&Expr> {
+    let expr = exprs.iter().find(|expr| {
+        expr.as_list().is_some()
+            && expr.range().is_some_and(|range| {
+                is_position_in_range(position, lsp_range_from_tan_range(range))
+            })
+    })?;
+
+    let terms = expr.as_list()?;
+    Some(enclosing_list(terms, position).unwrap_or(expr))
+}
+
+/// Expands the macros of the innermost list expression at the position, runs
+/// in the worker process, see `SandboxJob`. Only the forms before the
+/// top-level form at the position are evaluated, macros are defined before
+/// their use.
+pub fn expand_macro_sync(
+    input: &str,
+    document_uri: &str,
+    position: Position,
+    search_paths: &[PathBuf],
+    sandbox: bool,
+) -> Option<MacroExpansion> {
+    let exprs = parse_string_all(input).ok()?;
+
+    let index = exprs.iter().position(|expr| {
+        expr.range()
+            .is_some_and(|range| is_position_in_range(position, lsp_range_from_tan_range(range)))
+    })?;
+
+    let expr = enclosing_list(&exprs[index..=index], position)?;
+    let range = expr.range().map(lsp_range_from_tan_range)?;
+
+    // #insight the output of the analyzed module is discarded.
+    let (output_sender, _) = unbounded();
+    let mut context = make_sandboxed_context(document_uri, output_sender, sandbox).ok()?;
+
+    // #insight the definition errors are reported as diagnostics, not here.
+    let _ = parse_module_file(&exprs[..index], &mut context, search_paths);
+
+    let expansion = match compile(expr_clone(expr), &mut context) {
+        Ok(expanded) => MacroExpansion {
+            range,
+            expansion: Some(Formatter::for_dialect(&[expanded], Dialect::Code).format()),
+            errors: Vec::new(),
+        },
+        Err(errors) => MacroExpansion {
+            range,
+            expansion: None,
+            errors: errors.iter().map(|error| error.to_string()).collect(),
+        },
+    };
+
+    Some(expansion)
+}
+
+/// Expands the macros of the innermost list expression at the position, in a
+/// sandboxed worker process, with a time limit, see `expand_macro_sync`.
+pub fn expand_macro(
+    input: String,
+    document_uri: String,
+    position: Position,
+    search_paths: Vec<PathBuf>,
+    config: &EvalConfig,
+    cancellation: &Cancellation,
+) -> Result<Option<MacroExpansion>, EvalTimeout> {
+    let job = SandboxJob::ExpandMacro {
+        input,
+        document_uri,
+        position,
+        search_paths,
+        sandbox: config.sandbox,
+    };
+
+    let expansion: Option<Option<MacroExpansion>> =
+        run_cancellable(&job, config.analysis_timeout(), cancellation, |_| {})?;
+
+    Ok(expansion.unwrap_or_else(|| {
+        trace!("expansion worker cancelled or failed");
+        None
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Position;
+
+    use crate::{
+        config::EvalConfig,
+        eval::Cancellation,
+        expand_macro::{enclosing_list, expand_macro},
+        util::parse_string_all,
+    };
+
+    #[test]
+    fn expand_macro_usage() {
+        let input = "(let a 1)\n(let b (+ a 2))\n";
+
+        let exprs = parse_string_all(input).unwrap();
+        let expr = enclosing_list(&exprs, Position::new(1, 10)).unwrap();
+        assert_eq!(
+            expr.as_list().unwrap()[0].as_symbol(),
+            Some("+"),
+            "the innermost list is selected"
+        );
+
+        let expansion = expand_macro(
+            input.to_string(),
+            String::from("file:///project/main.tan"),
+            Position::new(1, 10),
+            Vec::new(),
+            &EvalConfig::default(),
+            &Cancellation::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(expansion.range.start, Position::new(1, 7));
+        assert!(expansion.expansion.unwrap().contains('+'));
+
+        let expansion = expand_macro(
+            input.to_string(),
+            String::from("file:///project/main.tan"),
+            Position::new(2, 0),
+            Vec::new(),
+            &EvalConfig::default(),
+            &Cancellation::default(),
+        )
+        .unwrap();
+        assert!(expansion.is_none());
+    }
+
+    #[test]
+    fn expand_macro_expands_defined_macros() {
+        let input = r#"
+(let unless (Macro [predicate body] '(if (not $predicate) $body)))
+(let c (unless false "yes"))
+"#;
+
+        let expansion = expand_macro(
+            input.to_string(),
+            String::from("file:///project/main.tan"),
+            Position::new(2, 9),
+            Vec::new(),
+            &EvalConfig::default(),
+            &Cancellation::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(expansion.range.start, Position::new(2, 7));
+        assert!(expansion.errors.is_empty());
+
+        let expanded = expansion.expansion.unwrap();
+        assert!(!expanded.contains("unless"), "the macro is expanded");
+        assert!(expanded.contains("(if (not false) \"yes\")"));
+    }
+}
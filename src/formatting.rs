@@ -0,0 +1,364 @@
+use std::{
+    io::{Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use tan::{error::Error, expr::Expr};
+use tan_formatting::{pretty::Formatter, types::Dialect};
+
+use crate::{
+    check::expand_paths, cli::FormatArgs, config::ServerConfig,
+    project_config::load_project_server_config, util::parse_string_all,
+};
+
+// #insight
+// The `format` subcommand uses the same formatter and dialect selection as
+// the editor, editor formatting and pre-commit formatting are in lockstep.
+
+/// The number of unchanged lines around each diff hunk.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// The maximum size of the LCS table, larger changes are diffed as a whole,
+/// i.e. all changed lines are deleted and re-inserted.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Formats the parsed expressions of a document in the given dialect.
+pub fn format_exprs(exprs: &[Expr], dialect: Dialect) -> String {
+    let formatter = Formatter::for_dialect(exprs, dialect);
+    formatter.format()
+}
+
+/// Formats a document in the given dialect.
+pub fn format_document(input: &str, dialect: Dialect) -> Result<String, Vec<Error>> {
+    let exprs = parse_string_all(input)?;
+    Ok(format_exprs(&exprs, dialect))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffOp {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Computes the line diff of two texts, with the longest common subsequence.
+fn lcs_diff_lines(old: &[&str], new: &[&str], offset: usize) -> Vec<DiffOp> {
+    let (n, m) = (old.len(), new.len());
+
+    // #insight lcs[i * (m + 1) + j] is the LCS length of old[i..] and new[j..].
+    let width = m + 1;
+    let mut lcs = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            ops.push(DiffOp::Equal(offset + i, offset + j));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            // #insight prefer deletions, removed lines precede added lines.
+            ops.push(DiffOp::Delete(offset + i));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(offset + j));
+            j += 1;
+        }
+    }
+
+    ops
+}
+
+/// Computes the line diff of two texts. The common prefix and suffix are
+/// skipped, formatting changes are typically local.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<DiffOp> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<DiffOp> = (0..prefix).map(|i| DiffOp::Equal(i, i)).collect();
+
+    if (old_changed.len() + 1) * (new_changed.len() + 1) > MAX_DIFF_CELLS {
+        ops.extend((0..old_changed.len()).map(|i| DiffOp::Delete(prefix + i)));
+        ops.extend((0..new_changed.len()).map(|j| DiffOp::Insert(prefix + j)));
+    } else {
+        ops.extend(lcs_diff_lines(old_changed, new_changed, prefix));
+    }
+
+    ops.extend((0..suffix).map(|k| DiffOp::Equal(old.len() - suffix + k, new.len() - suffix + k)));
+
+    ops
+}
+
+fn push_diff_line(output: &mut String, prefix: char, line: &str) {
+    output.push(prefix);
+    output.push_str(line);
+    if !line.ends_with('\n') {
+        output.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Computes a unified diff, returns an empty string if the texts are equal.
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, DiffOp::Equal(..)))
+        .map(|(index, _)| index)
+        .collect();
+
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // #insight group the changes into hunks, with context lines.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for index in changed {
+        let start = index.saturating_sub(DIFF_CONTEXT_LINES);
+        let end = (index + DIFF_CONTEXT_LINES + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut output = format!("--- a/{name}\n+++ b/{name}\n");
+
+    for (start, end) in hunks {
+        let hunk = &ops[start..end];
+
+        // #insight the (0-based) line positions before the hunk.
+        let (old_start, new_start) = ops[..start].iter().fold((0, 0), |(o, n), op| match op {
+            DiffOp::Equal(..) => (o + 1, n + 1),
+            DiffOp::Delete(_) => (o + 1, n),
+            DiffOp::Insert(_) => (o, n + 1),
+        });
+        let old_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Insert(_)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Delete(_)))
+            .count();
+
+        // #insight empty ranges are denoted by the line before the range.
+        let old_line = if old_count == 0 {
+            old_start
+        } else {
+            old_start + 1
+        };
+        let new_line = if new_count == 0 {
+            new_start
+        } else {
+            new_start + 1
+        };
+        output.push_str(&format!(
+            "@@ -{old_line},{old_count} +{new_line},{new_count} @@\n"
+        ));
+
+        for op in hunk {
+            match *op {
+                DiffOp::Equal(i, _) => push_diff_line(&mut output, ' ', old_lines[i]),
+                DiffOp::Delete(i) => push_diff_line(&mut output, '-', old_lines[i]),
+                DiffOp::Insert(j) => push_diff_line(&mut output, '+', new_lines[j]),
+            }
+        }
+    }
+
+    output
+}
+
+/// Formats the given paths in place, or reports the files that would change
+/// in check mode.
+pub fn run_format(args: &FormatArgs) -> anyhow::Result<ExitCode> {
+    let root = std::env::current_dir()?;
+    let config = load_project_server_config(&root).map_err(anyhow::Error::msg)?;
+
+    if args.stdin {
+        // #insight the path, if given, selects the dialect.
+        let name = args
+            .paths
+            .first()
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or_else(|| String::from("<stdin>"));
+
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+
+        let Ok(formatted) = format_document(&input, config.dialect_for(&name)) else {
+            eprintln!("{name}: cannot format, the input has parse errors");
+            return Ok(ExitCode::FAILURE);
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        if args.check {
+            let diff = unified_diff(&name, &input, &formatted);
+            write!(stdout, "{diff}")?;
+            return Ok(if diff.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+
+        write!(stdout, "{formatted}")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let paths = if args.paths.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        args.paths.clone()
+    };
+
+    let files = expand_paths(&paths, &config);
+
+    let failed = format_files(&files, &config, args.check, &mut std::io::stdout().lock())?;
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Formats the files in place, or writes the diffs in check mode. A file
+/// that cannot be read or formatted is reported and does not stop the run,
+/// returns true if any file failed (or would change, in check mode).
+pub fn format_files(
+    files: &[PathBuf],
+    config: &ServerConfig,
+    check: bool,
+    out: &mut impl Write,
+) -> std::io::Result<bool> {
+    let mut failed = false;
+
+    for path in files {
+        let name = path.to_string_lossy().replace('\\', "/");
+
+        let input = match std::fs::read_to_string(path) {
+            Ok(input) => input,
+            Err(error) => {
+                eprintln!("{name}: cannot format, {error}");
+                failed = true;
+                continue;
+            }
+        };
+
+        let Ok(formatted) = format_document(&input, config.dialect_for(&name)) else {
+            eprintln!("{name}: cannot format, the file has parse errors");
+            failed = true;
+            continue;
+        };
+
+        if formatted == input {
+            continue;
+        }
+
+        if check {
+            write!(out, "{}", unified_diff(&name, &input, &formatted))?;
+            failed = true;
+        } else if let Err(error) = std::fs::write(path, formatted) {
+            eprintln!("{name}: cannot write, {error}");
+            failed = true;
+        } else {
+            writeln!(out, "formatted {name}")?;
+        }
+    }
+
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::ServerConfig,
+        formatting::{diff_lines, format_files, unified_diff, DiffOp},
+    };
+
+    #[test]
+    fn unified_diff_usage() {
+        assert_eq!(unified_diff("main.tan", "(let a 1)\n", "(let a 1)\n"), "");
+
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        let diff = unified_diff("main.tan", old, new);
+        assert_eq!(
+            diff,
+            "--- a/main.tan\n+++ b/main.tan\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+
+        let diff = unified_diff("main.tan", "(let a 1)", "(let a 1)\n");
+        assert!(diff.contains("\\ No newline at end of file"));
+    }
+
+    #[test]
+    fn format_files_reports_unreadable_files() {
+        let dir = std::env::temp_dir().join(format!("tan-format-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.tan");
+        std::fs::write(&path, "(let   a 1)\n").unwrap();
+        let missing = dir.join("missing.tan");
+
+        // #insight the missing file fails the run, the other files are still checked.
+        let mut out = Vec::new();
+        let failed = format_files(&[missing, path], &ServerConfig::default(), true, &mut out);
+        assert!(failed.unwrap());
+        assert!(String::from_utf8(out).unwrap().contains("+++ b/"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diff_lines_large_inputs() {
+        // #insight a local change in a large file, the common lines are skipped.
+        let old: Vec<String> = (0..20_000).map(|i| format!("{i}\n")).collect();
+        let mut new = old.clone();
+        new[10_000] = String::from("changed\n");
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new: Vec<&str> = new.iter().map(String::as_str).collect();
+
+        let ops = diff_lines(&old, &new);
+        assert_eq!(ops.len(), 20_001);
+        assert_eq!(ops[10_000], DiffOp::Delete(10_000));
+        assert_eq!(ops[10_001], DiffOp::Insert(10_000));
+        assert_eq!(ops[20_000], DiffOp::Equal(19_999, 19_999));
+
+        // #insight larger changes fall back to deleting and re-inserting the lines.
+        let old: Vec<String> = (0..3_000).map(|i| format!("{i}\n")).collect();
+        let new: Vec<String> = (0..3_000).map(|i| format!("new {i}\n")).collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new: Vec<&str> = new.iter().map(String::as_str).collect();
+
+        let ops = diff_lines(&old, &new);
+        assert_eq!(ops.len(), 6_000);
+        assert_eq!(ops[0], DiffOp::Delete(0));
+        assert_eq!(ops[3_000], DiffOp::Insert(0));
+    }
+}
@@ -0,0 +1,311 @@
+use std::path::{Path, PathBuf};
+
+use lsp_types::{Diagnostic, DiagnosticSeverity, DocumentLink};
+use tan::expr::{expr_clone, Expr};
+use tracing::trace;
+
+use crate::util::{
+    let_bindings, lsp_range_from_tan_range, lsp_range_top, parse_string_all, uri_from_path,
+};
+
+// #insight
+// Tan modules are referenced with `(use /rng)` or `(use ./my-module)`. Paths
+// that start with `/` are resolved against the standard library root, all other
+// paths are resolved against the current module path, and then against the
+// module search paths of the project configuration.
+
+/// A `use` import found in the top-level expressions of a document.
+pub struct UseImport {
+    /// The module path, as written in the source, e.g. `/rng`.
+    pub path: String,
+    pub range: Option<tan::range::Range>,
+}
+
+// #todo also consider nested `use` forms?
+pub fn find_use_imports(exprs: &[Expr]) -> Vec<UseImport> {
+    let mut imports = Vec::new();
+
+    for expr in exprs {
+        let Some(terms) = expr.as_list() else {
+            continue;
+        };
+
+        let Some(op) = terms.first() else {
+            continue;
+        };
+
+        if op.as_symbol() != Some("use") {
+            continue;
+        }
+
+        let Some(path_expr) = terms.get(1) else {
+            continue;
+        };
+
+        // #insight accept both `(use /rng)` and `(use "/rng")`.
+        let Some(path) = path_expr.as_symbol().or_else(|| path_expr.as_string()) else {
+            continue;
+        };
+
+        imports.push(UseImport {
+            path: path.to_string(),
+            range: path_expr.range(),
+        });
+    }
+
+    imports
+}
+
+// #todo investigate how the tan CLI resolves the standard library root.
+pub fn std_lib_root() -> Option<PathBuf> {
+    let tan_root = std::env::var("TAN_ROOT").ok()?;
+    Some(PathBuf::from(tan_root).join("@std"))
+}
+
+fn resolve_module_candidate(candidate: PathBuf) -> Option<PathBuf> {
+    // #insight a module is either a directory of `.tan` files or a single file.
+    if candidate.is_dir() {
+        return Some(candidate);
+    }
+
+    let file = candidate.with_extension("tan");
+    if file.is_file() {
+        return Some(file);
+    }
+
+    None
+}
+
+/// Resolves a module path to a file or directory on disk.
+pub fn resolve_module_path(
+    path: &str,
+    current_module_path: &Path,
+    search_paths: &[PathBuf],
+) -> Option<PathBuf> {
+    if let Some(path) = path.strip_prefix('/') {
+        return resolve_module_candidate(std_lib_root()?.join(path));
+    }
+
+    std::iter::once(current_module_path)
+        .chain(search_paths.iter().map(PathBuf::as_path))
+        .find_map(|base| resolve_module_candidate(base.join(path)))
+}
+
+/// A top-level binding of an imported module, indexed without evaluation.
+pub struct IndexedBinding {
+    pub name: String,
+    pub value: Expr,
+    /// The file that defines the binding.
+    pub path: PathBuf,
+    /// The range of the binding name, in the defining file.
+    pub range: Option<tan::range::Range>,
+}
+
+/// Returns the source files of a module, the module is either a single file or
+/// a directory of `.tan` files.
+pub fn module_files(module_path: &Path) -> Vec<PathBuf> {
+    if module_path.is_file() {
+        return vec![module_path.to_path_buf()];
+    }
+
+    let Ok(entries) = std::fs::read_dir(module_path) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let name = path.to_string_lossy();
+            // #insight skip test files and data/config files.
+            name.ends_with(".tan")
+                && !name.ends_with(".test.tan")
+                && !name.ends_with(".data.tan")
+                && !name.ends_with(".config.tan")
+        })
+        .collect();
+
+    files.sort();
+
+    files
+}
+
+// #insight
+// The imported module is _not_ evaluated, only the top-level `let` definitions
+// are indexed, so top-level side effects of the module are not triggered.
+
+// #todo cache the indexed modules.
+pub fn index_module(module_path: &Path) -> Vec<IndexedBinding> {
+    let mut bindings = Vec::new();
+
+    for path in module_files(module_path) {
+        let Ok(input) = std::fs::read_to_string(&path) else {
+            trace!("cannot read module file `{}`", path.display());
+            continue;
+        };
+
+        let Ok(exprs) = parse_string_all(&input) else {
+            trace!("cannot parse module file `{}`", path.display());
+            continue;
+        };
+
+        for expr in &exprs {
+            for (name_expr, value) in let_bindings(expr) {
+                let Some(name) = name_expr.as_symbol() else {
+                    continue;
+                };
+
+                bindings.push(IndexedBinding {
+                    name: name.to_string(),
+                    value: expr_clone(value),
+                    path: path.clone(),
+                    range: name_expr.range(),
+                });
+            }
+        }
+    }
+
+    bindings
+}
+
+/// Returns the module prefix of an import path, e.g. `rng` for `/rng`.
+pub fn module_prefix(path: &str) -> &str {
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(path)
+}
+
+/// Resolves and indexes all modules imported by the given expressions. The
+/// names of the returned bindings are qualified with the module prefix, e.g.
+/// `rng/random`.
+pub fn imported_bindings(
+    exprs: &[Expr],
+    current_module_path: &Path,
+    search_paths: &[PathBuf],
+) -> Vec<IndexedBinding> {
+    let mut bindings = Vec::new();
+
+    for import in find_use_imports(exprs) {
+        let Some(module_path) =
+            resolve_module_path(&import.path, current_module_path, search_paths)
+        else {
+            continue;
+        };
+
+        let prefix = module_prefix(&import.path);
+
+        for mut binding in index_module(&module_path) {
+            binding.name = format!("{prefix}/{}", binding.name);
+            bindings.push(binding);
+        }
+    }
+
+    bindings
+}
+
+pub fn compute_document_links(
+    exprs: &[Expr],
+    current_module_path: &Path,
+    search_paths: &[PathBuf],
+) -> Vec<DocumentLink> {
+    let mut links = Vec::new();
+
+    for import in find_use_imports(exprs) {
+        let Some(tan_range) = import.range else {
+            continue;
+        };
+
+        let Some(resolved_path) =
+            resolve_module_path(&import.path, current_module_path, search_paths)
+        else {
+            continue;
+        };
+
+        links.push(DocumentLink {
+            range: lsp_range_from_tan_range(tan_range),
+            target: uri_from_path(&resolved_path),
+            tooltip: Some(resolved_path.display().to_string()),
+            data: None,
+        });
+    }
+
+    links
+}
+
+pub fn compute_import_diagnostics(
+    exprs: &[Expr],
+    current_module_path: &Path,
+    search_paths: &[PathBuf],
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for import in find_use_imports(exprs) {
+        if resolve_module_path(&import.path, current_module_path, search_paths).is_some() {
+            continue;
+        }
+
+        let range = if let Some(tan_range) = import.range {
+            lsp_range_from_tan_range(tan_range)
+        } else {
+            lsp_range_top()
+        };
+
+        diagnostics.push(Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some(String::from("tan")),
+            message: format!("cannot resolve module `{}`", import.path),
+            ..Default::default()
+        });
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        imports::{find_use_imports, imported_bindings, module_prefix},
+        util::parse_string_all,
+    };
+
+    #[test]
+    fn find_use_imports_usage() {
+        let input = r#"
+        (use /rng)
+        (let a 1)
+        (use "./utils")
+        "#;
+
+        let exprs = parse_string_all(input).unwrap();
+        let imports = find_use_imports(&exprs);
+        let paths: Vec<&str> = imports.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(paths, vec!["/rng", "./utils"]);
+    }
+
+    #[test]
+    fn imported_bindings_usage() {
+        let dir = std::env::temp_dir().join(format!(
+            "tan-language-server-imported-bindings-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("math.tan"),
+            "(let add (Func [x y] (+ x y)))\n(let pi 3.14)\n(writeln \"side-effect\")\n",
+        )
+        .unwrap();
+
+        assert_eq!(module_prefix("/rng"), "rng");
+        assert_eq!(module_prefix("./math"), "math");
+
+        let exprs = parse_string_all("(use ./math)\n(let a (math/add 1 2))").unwrap();
+        let bindings = imported_bindings(&exprs, &dir, &[]);
+        let names: Vec<&str> = bindings.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["math/add", "math/pi"]);
+        assert!(bindings.iter().all(|b| b.path.ends_with("math.tan")));
+        assert!(bindings.iter().all(|b| b.range.is_some()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
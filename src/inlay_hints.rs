@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use lsp_types::{
+    InlayHint, InlayHintKind, InlayHintLabel, InlayHintTooltip, MarkupContent, MarkupKind,
+};
+use serde::{Deserialize, Serialize};
+use tan::{context::Context, expr::Expr};
+
+use crate::util::{is_position_in_range, let_bindings, lsp_range_from_tan_range};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InlayHintsConfig {
+    /// Show parameter names at call sites of known functions.
+    pub parameter_names: bool,
+    /// Show inferred types after `let` bindings.
+    pub types: bool,
+}
+
+impl Default for InlayHintsConfig {
+    fn default() -> Self {
+        Self {
+            parameter_names: true,
+            types: true,
+        }
+    }
+}
+
+/// The data attached to an inlay hint, used to lazily compute the tooltip in
+/// `inlayHint/resolve`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum InlayHintData {
+    Parameter { func: String, params: Vec<String> },
+    Type { name: String, typ: String },
+}
+
+/// Returns the parameter names of a `(Func [a b] ...)` expression.
+pub fn func_params(value: &Expr) -> Option<Vec<String>> {
+    let terms = value.as_list()?;
+
+    if terms.first()?.as_symbol()? != "Func" {
+        return None;
+    }
+
+    // #insight the parser desugars `[a b]` to `(Array a b)`.
+    let params = terms.get(1)?.as_list()?;
+
+    if params.first()?.as_symbol()? != "Array" {
+        return None;
+    }
+
+    Some(
+        params[1..]
+            .iter()
+            .filter_map(|param| param.as_symbol().map(String::from))
+            .collect(),
+    )
+}
+
+/// Returns the statically known type of a `let` value, if any.
+pub fn static_type(value: &Expr, context: &Context) -> Option<String> {
+    if let Some(terms) = value.as_list() {
+        let op = terms.first()?.as_symbol()?;
+        return match op {
+            "Func" | "Array" | "Map" => Some(op.to_string()),
+            _ => None,
+        };
+    }
+
+    // #insight symbols need evaluation, their type is not statically known.
+    if value.as_symbol().is_some() {
+        return None;
+    }
+
+    // #insight for literals the dynamic type is the static type.
+    match value.dyn_type(context) {
+        Expr::Type(typ) => Some(typ),
+        _ => None,
+    }
+}
+
+/// Collects the parameter names of the top-level functions.
+pub fn collect_funcs(exprs: &[Expr]) -> HashMap<String, Vec<String>> {
+    let mut funcs = HashMap::new();
+
+    for expr in exprs {
+        for (name, value) in let_bindings(expr) {
+            if let (Some(name), Some(params)) = (name.as_symbol(), func_params(value)) {
+                funcs.insert(name.to_string(), params);
+            }
+        }
+    }
+
+    funcs
+}
+
+fn parameter_hints(expr: &Expr, funcs: &HashMap<String, Vec<String>>, hints: &mut Vec<InlayHint>) {
+    let Some(terms) = expr.as_list() else {
+        return;
+    };
+
+    let func = terms.first().and_then(|op| op.as_symbol());
+
+    if let Some((func, params)) = func.and_then(|func| Some((func, funcs.get(func)?))) {
+        for (arg, param) in terms[1..].iter().zip(params) {
+            // #insight skip redundant hints, e.g. `(zonk a b)` with params `[a b]`.
+            if arg.as_symbol() == Some(param.as_str()) {
+                continue;
+            }
+
+            let Some(range) = arg.range() else {
+                continue;
+            };
+
+            let data = InlayHintData::Parameter {
+                func: func.to_string(),
+                params: params.clone(),
+            };
+
+            hints.push(InlayHint {
+                position: lsp_range_from_tan_range(range).start,
+                label: InlayHintLabel::String(format!("{param}:")),
+                kind: Some(InlayHintKind::PARAMETER),
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: Some(true),
+                data: serde_json::to_value(data).ok(),
+            });
+        }
+    }
+
+    for term in terms {
+        parameter_hints(term, funcs, hints);
+    }
+}
+
+fn type_hints(exprs: &[Expr], context: &Context, hints: &mut Vec<InlayHint>) {
+    for expr in exprs {
+        for (name, value) in let_bindings(expr) {
+            let (Some(sym), Some(range)) = (name.as_symbol(), name.range()) else {
+                continue;
+            };
+
+            let Some(typ) = static_type(value, context) else {
+                continue;
+            };
+
+            let data = InlayHintData::Type {
+                name: sym.to_string(),
+                typ: typ.clone(),
+            };
+
+            hints.push(InlayHint {
+                position: lsp_range_from_tan_range(range).end,
+                label: InlayHintLabel::String(format!(": {typ}")),
+                kind: Some(InlayHintKind::TYPE),
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: None,
+                data: serde_json::to_value(data).ok(),
+            });
+        }
+    }
+}
+
+pub fn compute_inlay_hints(
+    exprs: &[Expr],
+    context: &Context,
+    range: lsp_types::Range,
+    config: &InlayHintsConfig,
+) -> Vec<InlayHint> {
+    let mut hints = Vec::new();
+
+    if config.parameter_names {
+        let funcs = collect_funcs(exprs);
+        for expr in exprs {
+            parameter_hints(expr, &funcs, &mut hints);
+        }
+    }
+
+    if config.types {
+        type_hints(exprs, context, &mut hints);
+    }
+
+    hints.retain(|hint| is_position_in_range(hint.position, range));
+
+    hints
+}
+
+/// Computes the tooltip of an inlay hint, in markdown if supported by the
+/// client.
+pub fn resolve_inlay_hint(mut hint: InlayHint, markdown: bool) -> InlayHint {
+    let Some(data) = hint
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<InlayHintData>(data).ok())
+    else {
+        return hint;
+    };
+
+    let tooltip = match data {
+        InlayHintData::Parameter { func, params } => format!("({func} {})", params.join(" ")),
+        InlayHintData::Type { name, typ } => format!("`{name}` is inferred as `{typ}`"),
+    };
+
+    hint.tooltip = Some(if markdown {
+        InlayHintTooltip::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: tooltip,
+        })
+    } else {
+        InlayHintTooltip::String(tooltip.replace('`', ""))
+    });
+
+    hint
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::InlayHintLabel;
+    use tan::context::Context;
+
+    use crate::{
+        inlay_hints::{compute_inlay_hints, InlayHintsConfig},
+        util::{lsp_range_whole_document, parse_string_all},
+    };
+
+    fn hint_labels(input: &str, config: &InlayHintsConfig) -> Vec<String> {
+        let exprs = parse_string_all(input).unwrap();
+        let context = Context::new();
+        compute_inlay_hints(&exprs, &context, lsp_range_whole_document(), config)
+            .into_iter()
+            .map(|hint| match hint.label {
+                InlayHintLabel::String(label) => label,
+                InlayHintLabel::LabelParts(_) => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compute_inlay_hints_usage() {
+        let input = r#"
+        (let zonk (Func [a b] (+ a b)))
+        (let b 2)
+        (let z (zonk 1 b))
+        "#;
+
+        let config = InlayHintsConfig::default();
+        let labels = hint_labels(input, &config);
+        assert!(labels.contains(&String::from("a:")));
+        // #insight `b` is passed as `b`, the hint is redundant.
+        assert!(!labels.contains(&String::from("b:")));
+        assert!(labels.contains(&String::from(": Func")));
+        assert!(labels.contains(&String::from(": Int")));
+
+        let config = InlayHintsConfig {
+            parameter_names: false,
+            types: true,
+        };
+        let labels = hint_labels(input, &config);
+        assert!(!labels.contains(&String::from("a:")));
+    }
+}
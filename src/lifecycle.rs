@@ -0,0 +1,98 @@
+use std::{process::ExitCode, time::Duration};
+
+use tracing::{info, warn};
+
+// #insight
+// The LSP lifecycle: the client sends `shutdown`, then `exit`. The server
+// exits with success only if `exit` follows `shutdown`. On `shutdown` the
+// background analyses are cancelled, evaluations are cancelled on timeout, see
+// `Cancellation`.
+
+/// How often the watchdog checks the parent process.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownStatus {
+    /// `exit` followed `shutdown`.
+    Clean,
+    /// `exit` without `shutdown`, or the client disconnected.
+    Unexpected,
+}
+
+impl ShutdownStatus {
+    pub fn exit_code(self) -> ExitCode {
+        match self {
+            ShutdownStatus::Clean => ExitCode::SUCCESS,
+            ShutdownStatus::Unexpected => ExitCode::FAILURE,
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn is_process_alive(pid: u32) -> bool {
+    // #insight signal 0 checks for the existence of the process, without a signal.
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return true;
+    }
+
+    // #insight EPERM, the process exists but belongs to another user.
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+pub fn is_process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, GetLastError, ERROR_ACCESS_DENIED, WAIT_TIMEOUT},
+        System::Threading::{OpenProcess, WaitForSingleObject, PROCESS_SYNCHRONIZE},
+    };
+
+    unsafe {
+        let handle = OpenProcess(PROCESS_SYNCHRONIZE, 0, pid);
+        if handle == 0 {
+            // #insight the process exists but cannot be opened, e.g. elevated.
+            return GetLastError() == ERROR_ACCESS_DENIED;
+        }
+
+        // #insight the process handle is signaled once the process exits.
+        let alive = WaitForSingleObject(handle, 0) == WAIT_TIMEOUT;
+        CloseHandle(handle);
+        alive
+    }
+}
+
+/// Exits the server if the parent (editor) process dies, e.g. if the editor
+/// crashes without sending `shutdown`.
+pub fn spawn_parent_process_watchdog(pid: u32) {
+    info!("Watching the parent process {pid}.");
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(WATCHDOG_INTERVAL);
+
+        if !is_process_alive(pid) {
+            warn!("The parent process {pid} exited, exiting.");
+            std::process::exit(1);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lifecycle::is_process_alive;
+
+    #[test]
+    fn is_process_alive_usage() {
+        assert!(is_process_alive(std::process::id()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_process_alive_detects_exited_processes() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(!is_process_alive(pid));
+
+        // #insight init, alive even if it cannot be signaled (EPERM).
+        assert!(is_process_alive(1));
+    }
+}
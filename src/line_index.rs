@@ -0,0 +1,412 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, CodeLens, Diagnostic,
+    DocumentLink, DocumentSymbol, DocumentSymbolResponse, GotoDefinitionResponse, InlayHint,
+    InlayHintLabel, Location, Position, PositionEncodingKind, Range, SymbolInformation, TextEdit,
+};
+
+use crate::{
+    code_lens::SHOW_REFERENCES_COMMAND,
+    eval::EvaluationResult,
+    expand_macro::MacroExpansion,
+    syntax_tree::{SyntaxNode, SyntaxTreeResult},
+    util::path_from_uri,
+};
+
+// #insight
+// Tan positions count characters (Unicode scalar values) per line, i.e. they
+// are UTF-32 LSP positions. The analysis works with these positions, they are
+// converted to (and from) the negotiated position encoding at the boundary
+// with the client.
+
+/// The length of a character, in the code units of the encoding.
+fn char_len(c: char, encoding: &PositionEncodingKind) -> u32 {
+    if *encoding == PositionEncodingKind::UTF8 {
+        c.len_utf8() as u32
+    } else if *encoding == PositionEncodingKind::UTF16 {
+        c.len_utf16() as u32
+    } else {
+        1
+    }
+}
+
+/// Converts between character positions and positions in a given encoding,
+/// for the lines of a text.
+pub struct LineIndex<'a> {
+    text: Cow<'a, str>,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: impl Into<Cow<'a, str>>) -> Self {
+        let text = text.into();
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+
+        Self { text, line_starts }
+    }
+
+    fn line(&self, line: u32) -> Option<&str> {
+        let start = *self.line_starts.get(line as usize)?;
+        let end = self
+            .line_starts
+            .get(line as usize + 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        Some(self.text[start..end].trim_end_matches(['\n', '\r']))
+    }
+
+    /// Converts a character position to a position in the encoding.
+    // #insight positions past the end of the line are kept past the end, e.g. `u32::MAX`.
+    pub fn encode(&self, position: Position, encoding: &PositionEncodingKind) -> Position {
+        let Some(line) = self.line(position.line) else {
+            return position;
+        };
+
+        let mut character = 0u32;
+        let mut chars = 0u32;
+        for c in line.chars() {
+            if chars == position.character {
+                break;
+            }
+            character += char_len(c, encoding);
+            chars += 1;
+        }
+
+        Position::new(
+            position.line,
+            character.saturating_add(position.character - chars),
+        )
+    }
+
+    /// Converts a position in the encoding to a character position.
+    pub fn decode(&self, position: Position, encoding: &PositionEncodingKind) -> Position {
+        let Some(line) = self.line(position.line) else {
+            return position;
+        };
+
+        let mut units = 0u32;
+        let mut character = 0u32;
+        for c in line.chars() {
+            if units >= position.character {
+                break;
+            }
+            units += char_len(c, encoding);
+            character += 1;
+        }
+
+        Position::new(
+            position.line,
+            character.saturating_add(position.character.saturating_sub(units)),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionDirection {
+    /// From character positions to the negotiated encoding.
+    ToClient,
+    /// From the negotiated encoding to character positions.
+    FromClient,
+}
+
+/// Converts the positions of the documents, open documents take precedence
+/// over the files on disk.
+pub struct PositionConverter<'a> {
+    encoding: PositionEncodingKind,
+    direction: ConversionDirection,
+    /// The line indexes of the open documents.
+    documents: &'a HashMap<String, LineIndex<'static>>,
+    /// The line indexes of the files on disk, the files are read on demand.
+    indexes: HashMap<String, LineIndex<'static>>,
+}
+
+impl<'a> PositionConverter<'a> {
+    pub fn new(
+        encoding: PositionEncodingKind,
+        direction: ConversionDirection,
+        documents: &'a HashMap<String, LineIndex<'static>>,
+    ) -> Self {
+        Self {
+            encoding,
+            direction,
+            documents,
+            indexes: HashMap::new(),
+        }
+    }
+
+    pub fn position(&mut self, uri: &str, position: Position) -> Position {
+        // #insight character positions are UTF-32 positions.
+        if self.encoding == PositionEncodingKind::UTF32 {
+            return position;
+        }
+
+        let documents = self.documents;
+        let index: &LineIndex = match documents.get(uri) {
+            Some(index) => index,
+            None => self.indexes.entry(uri.to_string()).or_insert_with(|| {
+                LineIndex::new(
+                    path_from_uri(uri)
+                        .and_then(|path| std::fs::read_to_string(path).ok())
+                        .unwrap_or_default(),
+                )
+            }),
+        };
+
+        match self.direction {
+            ConversionDirection::ToClient => index.encode(position, &self.encoding),
+            ConversionDirection::FromClient => index.decode(position, &self.encoding),
+        }
+    }
+
+    pub fn range(&mut self, uri: &str, range: Range) -> Range {
+        Range::new(
+            self.position(uri, range.start),
+            self.position(uri, range.end),
+        )
+    }
+}
+
+/// Values with positions, `uri` is the document of the positions, unless the
+/// value specifies a document, e.g. `Location`.
+pub trait ConvertPositions {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter);
+}
+
+impl<T: ConvertPositions> ConvertPositions for Vec<T> {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        for item in self {
+            item.convert_positions(uri, converter);
+        }
+    }
+}
+
+impl<T: ConvertPositions> ConvertPositions for Option<T> {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        if let Some(item) = self {
+            item.convert_positions(uri, converter);
+        }
+    }
+}
+
+impl ConvertPositions for Position {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        *self = converter.position(uri, *self);
+    }
+}
+
+impl ConvertPositions for Range {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        *self = converter.range(uri, *self);
+    }
+}
+
+impl ConvertPositions for Location {
+    fn convert_positions(&mut self, _uri: &str, converter: &mut PositionConverter) {
+        self.range = converter.range(self.uri.as_str(), self.range);
+    }
+}
+
+impl ConvertPositions for Diagnostic {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.range.convert_positions(uri, converter);
+        for info in self.related_information.iter_mut().flatten() {
+            info.location.convert_positions(uri, converter);
+        }
+    }
+}
+
+impl ConvertPositions for SymbolInformation {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.location.convert_positions(uri, converter);
+    }
+}
+
+impl ConvertPositions for DocumentSymbol {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.range.convert_positions(uri, converter);
+        self.selection_range.convert_positions(uri, converter);
+        self.children.convert_positions(uri, converter);
+    }
+}
+
+impl ConvertPositions for DocumentSymbolResponse {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        match self {
+            DocumentSymbolResponse::Flat(infos) => infos.convert_positions(uri, converter),
+            DocumentSymbolResponse::Nested(symbols) => symbols.convert_positions(uri, converter),
+        }
+    }
+}
+
+impl ConvertPositions for GotoDefinitionResponse {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        match self {
+            GotoDefinitionResponse::Scalar(location) => location.convert_positions(uri, converter),
+            GotoDefinitionResponse::Array(locations) => locations.convert_positions(uri, converter),
+            GotoDefinitionResponse::Link(links) => {
+                for link in links {
+                    let target_uri = link.target_uri.as_str();
+                    link.origin_selection_range
+                        .convert_positions(uri, converter);
+                    link.target_range.convert_positions(target_uri, converter);
+                    link.target_selection_range
+                        .convert_positions(target_uri, converter);
+                }
+            }
+        }
+    }
+}
+
+impl ConvertPositions for InlayHint {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.position.convert_positions(uri, converter);
+        if let InlayHintLabel::LabelParts(parts) = &mut self.label {
+            for part in parts {
+                part.location.convert_positions(uri, converter);
+            }
+        }
+        for edit in self.text_edits.iter_mut().flatten() {
+            edit.convert_positions(uri, converter);
+        }
+    }
+}
+
+impl ConvertPositions for TextEdit {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.range.convert_positions(uri, converter);
+    }
+}
+
+impl ConvertPositions for DocumentLink {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.range.convert_positions(uri, converter);
+    }
+}
+
+impl ConvertPositions for CodeLens {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.range.convert_positions(uri, converter);
+
+        // #insight the arguments of `tan.showReferences` are `[uri, position, locations]`.
+        let Some(command) = &mut self.command else {
+            return;
+        };
+        if command.command != SHOW_REFERENCES_COMMAND {
+            return;
+        }
+        let Some(arguments) = &mut command.arguments else {
+            return;
+        };
+        if let Some(position) = arguments.get_mut(1) {
+            if let Ok(mut value) = serde_json::from_value::<Position>(position.clone()) {
+                value.convert_positions(uri, converter);
+                *position = serde_json::to_value(value).unwrap();
+            }
+        }
+        if let Some(locations) = arguments.get_mut(2) {
+            if let Ok(mut value) = serde_json::from_value::<Vec<Location>>(locations.clone()) {
+                value.convert_positions(uri, converter);
+                *locations = serde_json::to_value(value).unwrap();
+            }
+        }
+    }
+}
+
+impl ConvertPositions for CallHierarchyItem {
+    fn convert_positions(&mut self, _uri: &str, converter: &mut PositionConverter) {
+        let uri = self.uri.to_string();
+        self.range.convert_positions(&uri, converter);
+        self.selection_range.convert_positions(&uri, converter);
+    }
+}
+
+impl ConvertPositions for CallHierarchyIncomingCall {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        // #insight the call sites are in the document of the caller.
+        let from_uri = self.from.uri.to_string();
+        self.from.convert_positions(uri, converter);
+        self.from_ranges.convert_positions(&from_uri, converter);
+    }
+}
+
+impl ConvertPositions for CallHierarchyOutgoingCall {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        // #insight the call sites are in the document of the requested item, i.e. `uri`.
+        self.to.convert_positions(uri, converter);
+        self.from_ranges.convert_positions(uri, converter);
+    }
+}
+
+impl ConvertPositions for EvaluationResult {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.range.convert_positions(uri, converter);
+    }
+}
+
+impl ConvertPositions for MacroExpansion {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.range.convert_positions(uri, converter);
+    }
+}
+
+impl ConvertPositions for SyntaxNode {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.range.convert_positions(uri, converter);
+        self.children.convert_positions(uri, converter);
+    }
+}
+
+impl ConvertPositions for SyntaxTreeResult {
+    fn convert_positions(&mut self, uri: &str, converter: &mut PositionConverter) {
+        self.nodes.convert_positions(uri, converter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, PositionEncodingKind};
+
+    use crate::line_index::LineIndex;
+
+    #[test]
+    fn line_index_usage() {
+        // #insight `👅` is 1 character, 2 UTF-16 code units and 4 bytes.
+        let text = "(let a 1)\n(let s \"👅 hi\") (let b 2)\n";
+        let index = LineIndex::new(text);
+
+        // The `b` after the emoji, at character 20.
+        let position = Position::new(1, 20);
+
+        let utf16 = index.encode(position, &PositionEncodingKind::UTF16);
+        assert_eq!(utf16, Position::new(1, 21));
+        assert_eq!(index.decode(utf16, &PositionEncodingKind::UTF16), position);
+
+        let utf8 = index.encode(position, &PositionEncodingKind::UTF8);
+        assert_eq!(utf8, Position::new(1, 23));
+        assert_eq!(index.decode(utf8, &PositionEncodingKind::UTF8), position);
+
+        assert_eq!(
+            index.encode(position, &PositionEncodingKind::UTF32),
+            position
+        );
+
+        // Positions before the emoji, and on ASCII lines, are unchanged.
+        let position = Position::new(1, 5);
+        assert_eq!(
+            index.encode(position, &PositionEncodingKind::UTF16),
+            position
+        );
+        let position = Position::new(0, 7);
+        assert_eq!(
+            index.encode(position, &PositionEncodingKind::UTF8),
+            position
+        );
+
+        // Positions past the end are kept past the end.
+        let end = Position::new(u32::MAX, u32::MAX);
+        assert_eq!(index.encode(end, &PositionEncodingKind::UTF16), end);
+    }
+}
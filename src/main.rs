@@ -1,34 +1,105 @@
+mod analysis;
+mod call_hierarchy;
+mod capabilities;
+mod check;
+mod cli;
+mod code_lens;
+mod completion;
+mod config;
+mod definition;
+mod diagnostics;
+mod eval;
+mod expand_macro;
+mod formatting;
+mod imports;
+mod inlay_hints;
+mod lifecycle;
+mod line_index;
+mod parse_cache;
+mod progress;
+mod project_config;
+mod query;
+mod record;
+mod references;
+mod sandbox;
 mod server;
+mod status;
+mod symbols;
+mod syntax_tree;
+#[cfg(test)]
+mod test_harness;
+mod transport;
 mod util;
+mod workspace;
 
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use std::{process::ExitCode, sync::Mutex};
 
-use crate::server::Server;
+use clap::Parser;
+use tracing_subscriber::{fmt, fmt::writer::BoxMakeWriter, prelude::*, EnvFilter};
 
-fn init_tracing() {
-    // #todo RUST_LOG is not passed from vscode, investigate.
+use crate::{
+    check::run_check,
+    cli::{Cli, Command},
+    formatting::run_format,
+    query::{run_definition, run_references, run_symbols},
+    record::run_replay,
+    sandbox::run_sandbox_worker,
+    server::Server,
+};
+
+fn init_tracing(cli: &Cli) -> anyhow::Result<()> {
+    // #insight RUST_LOG is not passed from vscode, use `--log-level` instead.
+
+    let writer = if let Some(path) = &cli.log_file {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        BoxMakeWriter::new(Mutex::new(file))
+    } else {
+        BoxMakeWriter::new(std::io::stderr)
+    };
 
     let fmt_layer = fmt::layer()
         .with_target(false)
-        .with_writer(std::io::stderr)
+        .with_writer(writer)
         .with_ansi(false);
 
-    let filter_layer = EnvFilter::try_from_default_env()
-        .or_else(|_| EnvFilter::try_new("info"))
-        .unwrap();
+    let filter_layer = if let Some(level) = &cli.log_level {
+        EnvFilter::try_new(level)?
+    } else {
+        EnvFilter::try_from_default_env()
+            .or_else(|_| EnvFilter::try_new("info"))
+            .unwrap()
+    };
 
     tracing_subscriber::registry()
         .with(filter_layer)
         .with(fmt_layer)
         .init();
+
+    Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    init_tracing();
+fn main() -> anyhow::Result<ExitCode> {
+    let cli = Cli::parse();
+
+    init_tracing(&cli)?;
+
+    match &cli.command {
+        Some(Command::Check(args)) => return run_check(args),
+        Some(Command::Format(args)) => return run_format(args),
+        Some(Command::Symbols(args)) => return run_symbols(args),
+        Some(Command::Definition(args)) => return run_definition(args),
+        Some(Command::References(args)) => return run_references(args),
+        Some(Command::Replay(args)) => return run_replay(args),
+        Some(Command::SandboxWorker) => return run_sandbox_worker(),
+        None => (),
+    }
 
     // #todo mut sucks here.
     let mut server = Server::new();
-    server.run()?;
+    let status = server.run(&cli.transport(), cli.record.as_deref())?;
 
-    Ok(())
+    Ok(status.exit_code())
 }
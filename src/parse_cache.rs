@@ -0,0 +1,409 @@
+use std::collections::{HashMap, VecDeque};
+
+use tan::{
+    error::Error,
+    expr::{annotate, annotate_range, expr_clone, Expr},
+    lexer::{token::TokenKind, Lexer},
+    range::Position,
+};
+
+use crate::util::parse_string_all;
+
+// #insight
+// A document is split into chunks of whole lines, each chunk ends with the
+// line that completes a top-level form, as delimited by the lexer tokens. Each chunk is parsed on its own, the
+// ranges of its forms are shifted to the position of the chunk. On change,
+// only the chunks with a different text are re-lexed and re-parsed, the forms
+// of the other chunks are reused, and shifted if the chunk moved. The parse
+// result is shared by the diagnostics, symbols, formatting, etc.
+
+/// A chunk of the last parse, `forms` is the number of its forms, `None` if
+/// the chunk is invalid.
+struct ParsedChunk {
+    start: Position,
+    text: String,
+    forms: Option<usize>,
+}
+
+/// The forms of a valid chunk of the last parse, at the chunk position.
+struct CachedChunk {
+    start: Position,
+    forms: Vec<Expr>,
+}
+
+pub struct ParsedDocument {
+    chunks: Vec<ParsedChunk>,
+    result: Result<Vec<Expr>, Vec<Error>>,
+    /// The forms of the valid chunks, kept while the document is invalid.
+    valid_forms: Vec<Expr>,
+}
+
+/// Splits the input into chunks of whole lines, returns the start line and
+/// the text of each chunk. The top-level forms are found from the tokens,
+/// annotations belong to the following form.
+fn split_chunks(input: &str) -> Vec<(usize, &str)> {
+    // #insight an input that cannot be lexed is a single (invalid) chunk.
+    let Ok(tokens) = Lexer::new(input).lex() else {
+        return if input.is_empty() {
+            Vec::new()
+        } else {
+            vec![(0, input)]
+        };
+    };
+
+    // #insight the first and last line of each top-level form.
+    let mut forms: Vec<(usize, usize)> = Vec::new();
+    let mut form_start: Option<usize> = None;
+    let mut depth = 0usize;
+    let mut last_line = 0;
+
+    for token in &tokens {
+        if matches!(
+            token.kind(),
+            TokenKind::Comment | TokenKind::MultiLineWhitespace
+        ) {
+            continue;
+        }
+
+        let range = token.range();
+        let start_line = *form_start.get_or_insert(range.start.line);
+        last_line = range.end.line;
+
+        match token.kind() {
+            TokenKind::LeftParen | TokenKind::LeftBracket | TokenKind::LeftBrace => {
+                depth += 1;
+                continue;
+            }
+            // #insight a stray closing delimiter ends a form of its own, the
+            // parser reports it.
+            TokenKind::RightParen | TokenKind::RightBracket | TokenKind::RightBrace => {
+                depth = depth.saturating_sub(1);
+            }
+            // #insight e.g. `#test` on the line above the form.
+            TokenKind::Annotation | TokenKind::Quote => continue,
+            _ => {}
+        }
+
+        if depth == 0 {
+            forms.push((start_line, last_line));
+            form_start = None;
+        }
+    }
+
+    // #insight an unclosed form (or annotation) extends to the last token.
+    if let Some(start_line) = form_start {
+        forms.push((start_line, last_line));
+    }
+
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+
+    let mut chunks = Vec::new();
+    let mut start_line = 0;
+    let mut forms = forms.into_iter().peekable();
+
+    while let Some((_, mut end_line)) = forms.next() {
+        // #insight forms that share a line belong to the same chunk.
+        while let Some((_, next_end)) = forms.next_if(|(next_start, _)| *next_start <= end_line) {
+            end_line = end_line.max(next_end);
+        }
+
+        // #insight blank and comment lines belong to the following chunk.
+        let start = line_starts[start_line];
+        let end = line_starts
+            .get(end_line + 1)
+            .copied()
+            .unwrap_or(input.len());
+        if end > start {
+            chunks.push((start_line, &input[start..end]));
+        }
+        start_line = end_line + 1;
+    }
+
+    if let Some(&start) = line_starts.get(start_line) {
+        if start < input.len() {
+            chunks.push((start_line, &input[start..]));
+        }
+    }
+
+    chunks
+}
+
+/// The position of a chunk, chunks start at the start of a line.
+fn chunk_position(index: usize, line: usize) -> Position {
+    Position {
+        index,
+        line,
+        col: 0,
+    }
+}
+
+fn shift_position(position: &mut Position, from: &Position, to: &Position) {
+    position.line = position.line - from.line + to.line;
+    position.index = position.index - from.index + to.index;
+}
+
+/// Moves the ranges of the expression (and its terms) from the chunk position
+/// `from` to the chunk position `to`, the columns are unchanged.
+fn shift_expr(expr: &Expr, from: &Position, to: &Position) -> Expr {
+    let mut shifted = match expr.unpack() {
+        Expr::List(terms) => Expr::List(
+            terms
+                .iter()
+                .map(|term| shift_expr(term, from, to))
+                .collect(),
+        ),
+        value => expr_clone(value),
+    };
+
+    if let Some(annotations) = expr.annotations() {
+        for (name, value) in annotations {
+            if name != "range" {
+                shifted = annotate(shifted, name.clone(), expr_clone(value));
+            }
+        }
+    }
+
+    match expr.range() {
+        Some(mut range) => {
+            shift_position(&mut range.start, from, to);
+            shift_position(&mut range.end, from, to);
+            annotate_range(shifted, range)
+        }
+        None => shifted,
+    }
+}
+
+/// Parses a chunk at its position in the document, i.e. the ranges of the
+/// forms (and errors) are document ranges.
+fn parse_chunk(start: &Position, text: &str) -> Result<Vec<Expr>, Vec<Error>> {
+    match parse_string_all(text) {
+        Ok(exprs) => Ok(exprs
+            .iter()
+            .map(|expr| shift_expr(expr, &chunk_position(0, 0), start))
+            .collect()),
+        Err(errors) => {
+            // #insight the error ranges are document ranges, the (rare) invalid
+            // chunk is parsed after comment lines in place of the preceding lines.
+            let input = format!("{}{text}", ";\n".repeat(start.line));
+            Err(parse_string_all(input).err().unwrap_or(errors))
+        }
+    }
+}
+
+impl ParsedDocument {
+    pub fn new(input: &str) -> Self {
+        let mut document = Self {
+            chunks: Vec::new(),
+            result: Ok(Vec::new()),
+            valid_forms: Vec::new(),
+        };
+        document.update(input);
+        document
+    }
+
+    pub fn result(&self) -> &Result<Vec<Expr>, Vec<Error>> {
+        &self.result
+    }
+
+    /// Updates the document to the new input, returns the number of the
+    /// re-parsed chunks.
+    pub fn update(&mut self, input: &str) -> usize {
+        let forms = match std::mem::replace(&mut self.result, Ok(Vec::new())) {
+            Ok(forms) => forms,
+            Err(_) => std::mem::take(&mut self.valid_forms),
+        };
+
+        // #insight the chunks are keyed by text, equal chunks are reused in order.
+        let mut forms = forms.into_iter();
+        let mut cached: HashMap<String, VecDeque<CachedChunk>> = HashMap::new();
+        for chunk in std::mem::take(&mut self.chunks) {
+            if let Some(count) = chunk.forms {
+                let chunk_forms = forms.by_ref().take(count).collect();
+                cached
+                    .entry(chunk.text)
+                    .or_default()
+                    .push_back(CachedChunk {
+                        start: chunk.start,
+                        forms: chunk_forms,
+                    });
+            }
+        }
+
+        let mut new_forms = Vec::new();
+        let mut errors = Vec::new();
+        let mut reparsed = 0;
+        let mut index = 0;
+
+        for (start_line, text) in split_chunks(input) {
+            let start = chunk_position(index, start_line);
+            index += text.len();
+
+            let reused = cached.get_mut(text).and_then(|chunks| chunks.pop_front());
+
+            let result = match reused {
+                Some(chunk)
+                    if (chunk.start.line, chunk.start.index) == (start.line, start.index) =>
+                {
+                    Ok(chunk.forms)
+                }
+                Some(chunk) => Ok(chunk
+                    .forms
+                    .iter()
+                    .map(|form| shift_expr(form, &chunk.start, &start))
+                    .collect()),
+                None => {
+                    reparsed += 1;
+                    parse_chunk(&start, text)
+                }
+            };
+
+            let forms = match result {
+                Ok(chunk_forms) => {
+                    let count = chunk_forms.len();
+                    new_forms.extend(chunk_forms);
+                    Some(count)
+                }
+                Err(mut chunk_errors) => {
+                    errors.append(&mut chunk_errors);
+                    None
+                }
+            };
+
+            self.chunks.push(ParsedChunk {
+                start,
+                text: text.to_string(),
+                forms,
+            });
+        }
+
+        if errors.is_empty() {
+            self.result = Ok(new_forms);
+        } else {
+            self.result = Err(errors);
+            self.valid_forms = new_forms;
+        }
+
+        reparsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tan_lints::compute_diagnostics;
+
+    use crate::{
+        parse_cache::{split_chunks, ParsedDocument},
+        util::{lsp_range_from_tan_range, parse_string_all},
+    };
+
+    #[test]
+    fn split_chunks_usage() {
+        let input = "; comment\n(let a 1)\n\n(let s \"(\n\")\n(let b [1\n 2]) ; )\n";
+        let chunks = split_chunks(input);
+        assert_eq!(
+            chunks,
+            vec![
+                (0, "; comment\n(let a 1)\n"),
+                (2, "\n(let s \"(\n\")\n"),
+                (5, "(let b [1\n 2]) ; )\n"),
+            ]
+        );
+
+        // #insight an annotation on its own line belongs to the next form.
+        let input = "(let a 1)\n#test\n(let f (Func [] 1))\n";
+        assert_eq!(
+            split_chunks(input),
+            vec![(0, "(let a 1)\n"), (1, "#test\n(let f (Func [] 1))\n")]
+        );
+
+        // #insight a stray closing delimiter is a chunk of its own.
+        let input = "(let a 1)\n)\n(let b 2)\n";
+        assert_eq!(
+            split_chunks(input),
+            vec![(0, "(let a 1)\n"), (1, ")\n"), (2, "(let b 2)\n")]
+        );
+    }
+
+    #[test]
+    fn parsed_document_matches_full_parse() {
+        let inputs = [
+            "#test\n(let f (Func [] 1))\n(let a 1) (let b 2)\n",
+            "; comment\n#String\n(let s \"(\")\n\n(let c [1\n 2])\n",
+            "(let a 1)\n)\n(let b 2)\n",
+            "(let a (+ 1 2)))\n(let b 2)\n",
+            "(let a 1)\n(let b (foo\n",
+        ];
+
+        for input in inputs {
+            let document = ParsedDocument::new(input);
+            let expected = parse_string_all(input);
+
+            match (document.result(), &expected) {
+                (Ok(forms), Ok(expected)) => {
+                    assert_eq!(format!("{forms:?}"), format!("{expected:?}"), "{input}");
+                }
+                (Err(_), Err(_)) => {
+                    // #insight the first error is reported at the same range.
+                    let diagnostics = compute_diagnostics(document.result());
+                    let expected = compute_diagnostics(&expected);
+                    assert_eq!(diagnostics[0].range, expected[0].range, "{input}");
+                }
+                (result, expected) => {
+                    panic!("{input:?}: {result:?} is not {expected:?}")
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parsed_document_usage() {
+        let mut document = ParsedDocument::new("(let a 1)\n\n(let b 2)\n(let c 3)\n");
+        assert_eq!(document.result().as_ref().unwrap().len(), 3);
+
+        // #insight only the changed form is re-parsed.
+        let reparsed = document.update("(let a 1)\n\n(let b 20)\n(let c 3)\n");
+        assert_eq!(reparsed, 1);
+
+        let forms = document.result().as_ref().unwrap();
+        assert_eq!(forms.len(), 3);
+        let range = lsp_range_from_tan_range(forms[1].range().unwrap());
+        assert_eq!(range.start.line, 2);
+        assert_eq!(forms[2].as_list().unwrap()[2].as_int(), Some(3));
+
+        // #insight a line insertion shifts the following forms, without re-parsing them.
+        let reparsed = document.update("(let z 0)\n(let a 1)\n\n(let b 20)\n(let c 3)\n");
+        assert_eq!(reparsed, 1);
+
+        let forms = document.result().as_ref().unwrap();
+        assert_eq!(forms.len(), 4);
+        let ranges: Vec<_> = forms
+            .iter()
+            .map(|form| lsp_range_from_tan_range(form.range().unwrap()))
+            .collect();
+        assert_eq!(
+            ranges
+                .iter()
+                .map(|range| (range.start.line, range.start.character, range.end.line))
+                .collect::<Vec<_>>(),
+            vec![(0, 0, 0), (1, 0, 1), (3, 0, 3), (4, 0, 4)]
+        );
+        // #insight the terms are shifted too.
+        let b = forms[2].as_list().unwrap();
+        assert_eq!(
+            lsp_range_from_tan_range(b[2].range().unwrap()).start,
+            lsp_types::Position::new(3, 7)
+        );
+        assert_eq!(b[2].as_int(), Some(20));
+
+        let reparsed = document.update("(let a 1)\n\n(let b 20\n(let c 3)\n");
+        assert_eq!(reparsed, 1);
+        assert!(document.result().is_err());
+
+        // #insight the valid chunks are kept while the document is invalid.
+        let reparsed = document.update("(let a 1)\n\n(let b 20)\n(let c 3)\n");
+        assert_eq!(reparsed, 2);
+        assert_eq!(document.result().as_ref().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,146 @@
+use lsp_server::{Connection, Message, RequestId};
+use lsp_types::{
+    notification::{Notification, Progress},
+    request::{Request, WorkDoneProgressCreate},
+    NumberOrString, ProgressParams, ProgressParamsValue, ProgressToken, WorkDoneProgress,
+    WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
+    WorkDoneProgressReport,
+};
+
+use crossbeam::channel::SendError;
+
+// #insight
+// Long operations report `$/progress` notifications, either with the
+// `workDoneToken` of the request or with a server-initiated token, created
+// with `window/workDoneProgress/create`. The client may cancel server-initiated
+// progress (if cancellable) with `window/workDoneProgress/cancel`.
+// #insight the progress begins once the client responds to the create
+// request, a cancellation sent before the response is not lost.
+
+/// The documents with more lines report formatting progress.
+pub const LARGE_DOCUMENT_LINES: usize = 2000;
+
+/// A work-done progress, ended on `end`.
+pub struct WorkDoneProgressReporter<'a> {
+    connection: &'a Connection,
+    token: ProgressToken,
+}
+
+impl<'a> WorkDoneProgressReporter<'a> {
+    /// Reports with the token supplied by the client.
+    pub fn with_token(connection: &'a Connection, token: ProgressToken) -> Self {
+        Self { connection, token }
+    }
+
+    /// Creates a server-initiated progress, the token is derived from the
+    /// request id.
+    pub fn create(connection: &'a Connection, id: i32) -> Result<Self, SendError<Message>> {
+        let token = NumberOrString::String(format!("tan/{id}"));
+
+        let params = WorkDoneProgressCreateParams {
+            token: token.clone(),
+        };
+        let req = lsp_server::Request::new(
+            RequestId::from(id),
+            WorkDoneProgressCreate::METHOD.to_owned(),
+            params,
+        );
+        connection.sender.send(Message::Request(req))?;
+
+        Ok(Self { connection, token })
+    }
+
+    pub fn token(&self) -> &ProgressToken {
+        &self.token
+    }
+
+    fn send(&self, progress: WorkDoneProgress) -> Result<(), SendError<Message>> {
+        let params = ProgressParams {
+            token: self.token.clone(),
+            value: ProgressParamsValue::WorkDone(progress),
+        };
+
+        let notification = lsp_server::Notification {
+            method: Progress::METHOD.to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        };
+
+        self.connection
+            .sender
+            .send(Message::Notification(notification))
+    }
+
+    pub fn begin(&self, title: &str, cancellable: bool) -> Result<(), SendError<Message>> {
+        self.send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: title.to_string(),
+            cancellable: Some(cancellable),
+            message: None,
+            percentage: Some(0),
+        }))
+    }
+
+    pub fn report(&self, message: &str, percentage: u32) -> Result<(), SendError<Message>> {
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: None,
+            message: Some(message.to_string()),
+            percentage: Some(percentage),
+        }))
+    }
+
+    pub fn end(self, message: &str) -> Result<(), SendError<Message>> {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd {
+            message: Some(message.to_string()),
+        }))
+    }
+}
+
+/// The percentage of `done` of `total` items.
+pub fn percentage(done: usize, total: usize) -> u32 {
+    if total == 0 {
+        100
+    } else {
+        (done * 100 / total) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_server::{Connection, Message};
+    use lsp_types::{ProgressParams, ProgressParamsValue, WorkDoneProgress};
+
+    use crate::progress::{percentage, WorkDoneProgressReporter};
+
+    #[test]
+    fn work_done_progress_reporter_usage() {
+        let (server, client) = Connection::memory();
+
+        let progress = WorkDoneProgressReporter::create(&server, 7).unwrap();
+        progress.begin("Indexing", true).unwrap();
+        progress.report("main.tan", percentage(1, 2)).unwrap();
+        progress.end("indexed").unwrap();
+
+        let Ok(Message::Request(req)) = client.receiver.try_recv() else {
+            panic!("expected the create request");
+        };
+        assert_eq!(req.method, "window/workDoneProgress/create");
+
+        let values: Vec<WorkDoneProgress> = client
+            .receiver
+            .try_iter()
+            .map(|msg| {
+                let Message::Notification(notification) = msg else {
+                    panic!("expected a notification");
+                };
+                let params: ProgressParams = serde_json::from_value(notification.params).unwrap();
+                let ProgressParamsValue::WorkDone(value) = params.value;
+                value
+            })
+            .collect();
+
+        assert!(matches!(values[0], WorkDoneProgress::Begin(_)));
+        assert!(
+            matches!(&values[1], WorkDoneProgress::Report(report) if report.percentage == Some(50))
+        );
+        assert!(matches!(values[2], WorkDoneProgress::End(_)));
+    }
+}
@@ -0,0 +1,292 @@
+use std::path::Path;
+
+use serde_json::{Map, Number, Value};
+use tan::expr::Expr;
+
+use crate::{
+    config::ServerConfig,
+    util::{parse_string_all, path_from_uri},
+};
+
+// #insight
+// The project configuration lives in `tan.config.tan` at the workspace root,
+// it is shared by the whole team. It uses the same schema as the editor
+// settings, with kebab-case keys, e.g.
+//
+// {
+//     :lints {
+//         :rules { :snake-case :off }
+//     }
+//     :formatter {
+//         :dialects { "*.style.tan" :data }
+//     }
+//     :modules {
+//         :search-paths ["lib" "vendor"]
+//     }
+// }
+//
+// Project settings take precedence over the editor settings.
+
+pub const PROJECT_CONFIG_FILE_NAME: &str = "tan.config.tan";
+
+// #insight the keys of these maps are user-defined and kept verbatim.
+const VERBATIM_KEY_MAPS: [&str; 2] = ["rules", "dialects"];
+
+// #insight a checked-out repository should not be able to turn off the
+// sandbox, or raise the time limits, the evaluation settings are only read
+// from the editor settings.
+const IGNORED_SECTIONS: [&str; 1] = ["eval"];
+
+/// Returns true if the URI is the project configuration file of the workspace,
+/// nested files with the same name are ignored.
+pub fn is_project_config_uri(uri: &str, workspace_root: Option<&Path>) -> bool {
+    let (Some(root), Some(path)) = (workspace_root, path_from_uri(uri)) else {
+        return false;
+    };
+
+    path == root.join(PROJECT_CONFIG_FILE_NAME)
+}
+
+/// Converts a kebab-case key to camelCase, e.g. `search-paths` to
+/// `searchPaths`.
+pub fn kebab_to_camel_case(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+
+    for c in key.chars() {
+        if c == '-' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+
+    camel
+}
+
+fn expr_key(expr: &Expr) -> String {
+    if let Some(key) = expr.as_string().or_else(|| expr.as_symbol()) {
+        return key.to_string();
+    }
+
+    // #insight key-symbols are printed as `:key`.
+    expr.to_string().trim_start_matches(':').to_string()
+}
+
+/// Converts a (data) expression to JSON.
+pub fn expr_to_json(expr: &Expr, verbatim_keys: bool) -> Value {
+    if let Some(terms) = expr.as_list() {
+        // #insight the parser desugars `{...}` to `(Map ...)` and `[...]` to `(Array ...)`.
+        return match terms.first().and_then(|op| op.as_symbol()) {
+            Some("Map") => {
+                let mut map = Map::new();
+                for pair in terms[1..].chunks(2) {
+                    let [key, value] = pair else {
+                        continue;
+                    };
+                    let key = expr_key(key);
+                    let value = expr_to_json(value, VERBATIM_KEY_MAPS.contains(&key.as_str()));
+                    let key = if verbatim_keys {
+                        key
+                    } else {
+                        kebab_to_camel_case(&key)
+                    };
+                    map.insert(key, value);
+                }
+                Value::Object(map)
+            }
+            Some("Array") => Value::Array(
+                terms[1..]
+                    .iter()
+                    .map(|term| expr_to_json(term, false))
+                    .collect(),
+            ),
+            _ => Value::Null,
+        };
+    }
+
+    if let Some(s) = expr.as_string() {
+        return Value::String(s.to_string());
+    }
+
+    if let Some(b) = expr.as_bool() {
+        return Value::Bool(b);
+    }
+
+    if let Some(n) = expr.as_int() {
+        return Value::Number(n.into());
+    }
+
+    if let Some(n) = expr.as_float() {
+        return Number::from_f64(n).map_or(Value::Null, Value::Number);
+    }
+
+    // #insight symbols and key-symbols, e.g. `:off`.
+    Value::String(expr_key(expr))
+}
+
+/// Parses the project configuration to JSON, without the ignored sections,
+/// see `IGNORED_SECTIONS`.
+pub fn parse_project_config(input: &str) -> Result<Value, String> {
+    let exprs = parse_string_all(input).map_err(|errors| {
+        errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    })?;
+
+    let Some(expr) = exprs.iter().find(|expr| expr.as_list().is_some()) else {
+        return Ok(Value::Object(Map::new()));
+    };
+
+    match expr_to_json(expr, false) {
+        Value::Object(mut sections) => {
+            for section in IGNORED_SECTIONS {
+                sections.remove(section);
+            }
+            Ok(Value::Object(sections))
+        }
+        _ => Err(format!("{PROJECT_CONFIG_FILE_NAME} should contain a map")),
+    }
+}
+
+pub fn read_project_config(root: &Path) -> Option<Result<Value, String>> {
+    let input = std::fs::read_to_string(root.join(PROJECT_CONFIG_FILE_NAME)).ok()?;
+    Some(parse_project_config(&input))
+}
+
+/// Reads the configuration of a project without editor settings, e.g. for
+/// the command-line subcommands. Invalid fields fall back to their defaults,
+/// like in the server, the errors are printed as warnings.
+pub fn load_project_server_config(root: &Path) -> Result<ServerConfig, String> {
+    match read_project_config(root) {
+        None => Ok(ServerConfig::default()),
+        Some(Ok(settings)) => {
+            let (config, errors) = ServerConfig::from_value(&settings);
+            for error in errors {
+                eprintln!("warning: invalid {PROJECT_CONFIG_FILE_NAME}: {error}");
+            }
+            Ok(config)
+        }
+        Some(Err(error)) => Err(format!("invalid {PROJECT_CONFIG_FILE_NAME}: {error}")),
+    }
+}
+
+/// Deep-merges `overlay` into `base`, values of `overlay` take precedence.
+pub fn merge_json(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_json(base.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use crate::{
+        config::{RuleSeverity, ServerConfig},
+        project_config::{
+            is_project_config_uri, kebab_to_camel_case, load_project_server_config, merge_json,
+            parse_project_config, PROJECT_CONFIG_FILE_NAME,
+        },
+    };
+
+    #[test]
+    fn kebab_to_camel_case_usage() {
+        assert_eq!(kebab_to_camel_case("search-paths"), "searchPaths");
+        assert_eq!(kebab_to_camel_case("lints"), "lints");
+    }
+
+    #[test]
+    fn parse_project_config_usage() {
+        let input = r#"
+        {
+            :lints {
+                :rules { :snake-case :off }
+            }
+            :modules {
+                :search-paths ["lib" "vendor"]
+            }
+            :eval {
+                :timeout-ms 500
+            }
+        }
+        "#;
+
+        let mut value = json!({ "eval": { "sandbox": false, "timeoutMs": 1000 } });
+        merge_json(&mut value, &parse_project_config(input).unwrap());
+
+        let (config, errors) = ServerConfig::from_value(&value);
+        assert!(errors.is_empty());
+        assert_eq!(
+            config.lints.rules.get("snake-case"),
+            Some(&RuleSeverity::Off)
+        );
+        assert_eq!(config.modules.search_paths, vec!["lib", "vendor"]);
+        // #insight the evaluation settings of the project are ignored.
+        assert_eq!(config.eval.timeout_ms, 1000);
+        assert!(!config.eval.sandbox);
+
+        let (config, _) = ServerConfig::from_value(
+            &parse_project_config("{ :eval { :sandbox false } }").unwrap(),
+        );
+        assert!(config.eval.sandbox);
+
+        assert!(parse_project_config("[1 2 3]").is_err());
+    }
+
+    #[test]
+    fn load_project_server_config_skips_invalid_fields() {
+        let root =
+            std::env::temp_dir().join(format!("tan-ls-project-config-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        // #insight `:enabled` should be a boolean, the valid fields are kept.
+        std::fs::write(
+            root.join(PROJECT_CONFIG_FILE_NAME),
+            "{ :lints { :enabled 1 } :modules { :search-paths [\"lib\"] } }\n",
+        )
+        .unwrap();
+
+        let config = load_project_server_config(&root).unwrap();
+        assert!(config.lints.enabled);
+        assert_eq!(config.modules.search_paths, vec!["lib"]);
+
+        std::fs::write(root.join(PROJECT_CONFIG_FILE_NAME), "[1 2 3]\n").unwrap();
+        assert!(load_project_server_config(&root).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn is_project_config_uri_usage() {
+        let root = Path::new("/project");
+        assert!(is_project_config_uri(
+            "file:///project/tan.config.tan",
+            Some(root)
+        ));
+        assert!(!is_project_config_uri(
+            "file:///project/vendor/lib/tan.config.tan",
+            Some(root)
+        ));
+        assert!(!is_project_config_uri(
+            "file:///project/main.tan",
+            Some(root)
+        ));
+        assert!(!is_project_config_uri(
+            "file:///project/tan.config.tan",
+            None
+        ));
+    }
+}
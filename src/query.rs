@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use lsp_types::{Position, PositionEncodingKind, Uri};
+use serde::Serialize;
+
+use crate::{
+    analysis::analyze_module,
+    cli::{PositionArgs, ReferencesArgs, SymbolsArgs},
+    config::ServerConfig,
+    definition::compute_definition,
+    eval::Cancellation,
+    imports::imported_bindings,
+    line_index::{ConversionDirection, ConvertPositions, LineIndex, PositionConverter},
+    parse_cache::ParsedDocument,
+    project_config::load_project_server_config,
+    references::compute_references,
+    symbols::compute_document_symbol_response,
+    util::{document_module_path, parse_string_all, uri_from_path},
+    workspace::WorkspaceIndex,
+};
+
+// #insight
+// The query subcommands print exactly the result of the corresponding LSP
+// request as JSON, e.g. for scripts and code-review bots. The current
+// directory is the workspace root. The input columns count characters, the
+// output positions are UTF-16 positions, the LSP default.
+
+/// A document of the workspace, the target of a query.
+struct QueryDocument {
+    root: PathBuf,
+    config: ServerConfig,
+    uri: Uri,
+    input: String,
+}
+
+impl QueryDocument {
+    fn open(path: &Path) -> anyhow::Result<Self> {
+        let root = std::env::current_dir()?.canonicalize()?;
+        let config = load_project_server_config(&root).map_err(anyhow::Error::msg)?;
+
+        let input = std::fs::read_to_string(path)?;
+        let uri = uri_from_path(&path.canonicalize()?)
+            .ok_or_else(|| anyhow::anyhow!("invalid path `{}`", path.display()))?;
+
+        Ok(Self {
+            root,
+            config,
+            uri,
+            input,
+        })
+    }
+
+    /// Indexes the workspace, the parsed document takes precedence over the
+    /// file on disk.
+    fn index(&self) -> (WorkspaceIndex, HashMap<String, ParsedDocument>) {
+        let mut index = WorkspaceIndex::default();
+        index.update(Some(&self.root), &self.config.workspace, |_, _| {
+            ControlFlow::Continue(())
+        });
+        let documents = HashMap::from([(self.uri.to_string(), ParsedDocument::new(&self.input))]);
+        (index, documents)
+    }
+
+    /// Converts the positions of a result to UTF-16 positions.
+    fn encode_positions<T: ConvertPositions>(&self, mut value: T) -> T {
+        let documents = HashMap::from([(self.uri.to_string(), LineIndex::new(self.input.clone()))]);
+        let mut converter = PositionConverter::new(
+            PositionEncodingKind::UTF16,
+            ConversionDirection::ToClient,
+            &documents,
+        );
+        value.convert_positions(self.uri.as_str(), &mut converter);
+        value
+    }
+}
+
+/// Parses a `<file>:<line>:<col>` location, lines and columns are 1-based.
+pub fn parse_file_position(location: &str) -> anyhow::Result<(PathBuf, Position)> {
+    let mut parts = location.rsplitn(3, ':');
+    let (Some(col), Some(line), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+        anyhow::bail!("invalid location `{location}`, expected `<file>:<line>:<col>`");
+    };
+
+    let line: u32 = line.parse()?;
+    let col: u32 = col.parse()?;
+
+    if line == 0 || col == 0 {
+        anyhow::bail!("invalid location `{location}`, lines and columns are 1-based");
+    }
+
+    Ok((PathBuf::from(path), Position::new(line - 1, col - 1)))
+}
+
+fn print_json(value: &impl Serialize) -> anyhow::Result<ExitCode> {
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", serde_json::to_string_pretty(value)?)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn run_symbols(args: &SymbolsArgs) -> anyhow::Result<ExitCode> {
+    let document = QueryDocument::open(&args.file)?;
+    let exprs = parse_string_all(&document.input).unwrap_or_default();
+
+    let Ok(analysis) = analyze_module(
+        document.input.clone(),
+        document.uri.to_string(),
+        document.config.module_search_paths(Some(&document.root)),
+        &document.config.eval,
+        &Cancellation::default(),
+    ) else {
+        anyhow::bail!("analysis timed out");
+    };
+
+    let response = compute_document_symbol_response(
+        &document.uri,
+        &exprs,
+        Some(analysis.bindings),
+        args.hierarchical,
+    );
+
+    print_json(&document.encode_positions(response))
+}
+
+pub fn run_definition(args: &PositionArgs) -> anyhow::Result<ExitCode> {
+    let (path, position) = parse_file_position(&args.location)?;
+    let document = QueryDocument::open(&path)?;
+
+    let (index, documents) = document.index();
+    let sources = index.sources(&documents);
+
+    let response = match documents[document.uri.as_str()].result() {
+        Ok(exprs) => {
+            let imports = imported_bindings(
+                exprs,
+                &document_module_path(document.uri.as_str())?,
+                &document.config.module_search_paths(Some(&document.root)),
+            );
+            compute_definition(&document.uri, exprs, position, &sources, &imports)
+        }
+        Err(_) => None,
+    };
+
+    print_json(&document.encode_positions(response))
+}
+
+pub fn run_references(args: &ReferencesArgs) -> anyhow::Result<ExitCode> {
+    let (path, position) = parse_file_position(&args.position.location)?;
+    let document = QueryDocument::open(&path)?;
+
+    let (index, documents) = document.index();
+    let sources = index.sources(&documents);
+
+    let response = match documents[document.uri.as_str()].result() {
+        Ok(exprs) => compute_references(
+            &document.uri,
+            exprs,
+            position,
+            &sources,
+            args.include_declaration,
+        ),
+        Err(_) => None,
+    };
+
+    print_json(&document.encode_positions(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use lsp_types::Position;
+
+    use crate::query::parse_file_position;
+
+    #[test]
+    fn parse_file_position_usage() {
+        let (path, position) = parse_file_position("src/main.tan:3:7").unwrap();
+        assert_eq!(path, PathBuf::from("src/main.tan"));
+        assert_eq!(position, Position::new(2, 6));
+
+        assert!(parse_file_position("src/main.tan").is_err());
+        assert!(parse_file_position("src/main.tan:0:1").is_err());
+    }
+}
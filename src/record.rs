@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::Instant,
+};
+
+use crossbeam::channel::unbounded;
+use lsp_server::{Connection, Message, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::{cli::ReplayArgs, formatting::unified_diff, server::Server};
+
+// #insight
+// A trace is a JSONL file, one JSON-RPC message per line, with the time (in
+// milliseconds since the start of the session) and the direction. Traces
+// contain the documents of the session, and machine-specific paths, replay
+// them on the machine (and workspace) they were recorded on.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    /// From the client to the server.
+    In,
+    /// From the server to the client.
+    Out,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TraceEntry {
+    pub time: u128,
+    pub direction: Direction,
+    pub message: Message,
+}
+
+struct TraceWriter {
+    start: Instant,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl TraceWriter {
+    fn write(&self, direction: Direction, message: &Message) {
+        let entry = serde_json::json!({
+            "time": self.start.elapsed().as_millis(),
+            "direction": direction,
+            "message": message,
+        });
+
+        let mut writer = self.writer.lock().expect("not poisoned");
+        // #insight flush every entry, the trace should survive a crash.
+        let _ = writeln!(writer, "{entry}").and_then(|_| writer.flush());
+    }
+}
+
+/// Records all the messages of a connection to a trace file. Returns the
+/// recording connection, and the thread that forwards the outgoing messages,
+/// it ends when the recording connection is dropped.
+pub fn record_connection(
+    connection: Connection,
+    path: &Path,
+) -> std::io::Result<(Connection, JoinHandle<()>)> {
+    let trace = Arc::new(TraceWriter {
+        start: Instant::now(),
+        writer: Mutex::new(BufWriter::new(File::create(path)?)),
+    });
+
+    let Connection { sender, receiver } = connection;
+
+    let (incoming_sender, incoming_receiver) = unbounded();
+    let incoming_trace = trace.clone();
+    // #insight the incoming thread ends when the client disconnects, it is detached.
+    std::thread::spawn(move || {
+        for message in receiver {
+            incoming_trace.write(Direction::In, &message);
+            if incoming_sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let (outgoing_sender, outgoing_receiver) = unbounded::<Message>();
+    let outgoing = std::thread::spawn(move || {
+        for message in outgoing_receiver {
+            trace.write(Direction::Out, &message);
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let connection = Connection {
+        sender: outgoing_sender,
+        receiver: incoming_receiver,
+    };
+
+    Ok((connection, outgoing))
+}
+
+pub fn read_trace(path: &Path) -> anyhow::Result<Vec<TraceEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|error| {
+            anyhow::anyhow!("invalid trace entry at line {}: {error}", index + 1)
+        })?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Feeds the incoming messages of a trace to a fresh server, returns the
+/// outgoing messages of the server.
+pub fn replay(entries: &[TraceEntry]) -> anyhow::Result<Vec<Message>> {
+    let (server_connection, client_connection) = Connection::memory();
+    let Connection { sender, receiver } = client_connection;
+
+    let server = std::thread::spawn(move || Server::new().serve(server_connection));
+
+    for entry in entries {
+        if entry.direction == Direction::In {
+            sender.send(entry.message.clone())?;
+        }
+    }
+
+    // #insight the server stops when the client disconnects, if the trace has no `exit`.
+    drop(sender);
+
+    server
+        .join()
+        .map_err(|_| anyhow::anyhow!("the server panicked"))??;
+
+    Ok(receiver.try_iter().collect())
+}
+
+/// Compares the recorded responses with the replayed responses, matched by
+/// request id. Returns a description of each difference.
+pub fn diff_responses(entries: &[TraceEntry], replayed: &[Message]) -> Vec<String> {
+    let replayed: HashMap<String, &Response> = replayed
+        .iter()
+        .filter_map(|message| match message {
+            Message::Response(resp) => Some((resp.id.to_string(), resp)),
+            _ => None,
+        })
+        .collect();
+
+    let mut diffs = Vec::new();
+
+    for entry in entries {
+        let (Direction::Out, Message::Response(recorded)) = (entry.direction, &entry.message)
+        else {
+            continue;
+        };
+
+        let id = recorded.id.to_string();
+
+        let Some(actual) = replayed.get(&id) else {
+            diffs.push(format!("missing response {id}\n"));
+            continue;
+        };
+
+        let recorded = serde_json::to_string_pretty(recorded).unwrap_or_default() + "\n";
+        let actual = serde_json::to_string_pretty(actual).unwrap_or_default() + "\n";
+
+        if recorded != actual {
+            diffs.push(unified_diff(
+                &format!("response-{id}.json"),
+                &recorded,
+                &actual,
+            ));
+        }
+    }
+
+    diffs
+}
+
+pub fn run_replay(args: &ReplayArgs) -> anyhow::Result<ExitCode> {
+    let entries = read_trace(&args.trace)?;
+    let replayed = replay(&entries)?;
+    let diffs = diff_responses(&entries, &replayed);
+
+    let mut stdout = std::io::stdout().lock();
+    for diff in &diffs {
+        write!(stdout, "{diff}")?;
+    }
+
+    let responses = entries
+        .iter()
+        .filter(|entry| {
+            entry.direction == Direction::Out && matches!(entry.message, Message::Response(_))
+        })
+        .count();
+    writeln!(
+        stdout,
+        "{} responses replayed, {} differ",
+        responses,
+        diffs.len()
+    )?;
+
+    Ok(if diffs.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_server::{Message, Notification, Request, RequestId};
+    use serde_json::json;
+
+    use crate::record::{diff_responses, replay, Direction, TraceEntry};
+
+    fn incoming(message: Message) -> TraceEntry {
+        TraceEntry {
+            time: 0,
+            direction: Direction::In,
+            message,
+        }
+    }
+
+    #[test]
+    fn replay_usage() {
+        let uri = "file:///project/main.tan";
+
+        let mut entries = vec![
+            incoming(Message::Request(Request::new(
+                RequestId::from(1),
+                String::from("initialize"),
+                json!({ "processId": null, "rootUri": null, "capabilities": {} }),
+            ))),
+            incoming(Message::Notification(Notification::new(
+                String::from("initialized"),
+                json!({}),
+            ))),
+            incoming(Message::Notification(Notification::new(
+                String::from("textDocument/didOpen"),
+                json!({
+                    "textDocument": { "uri": uri, "languageId": "tan", "version": 1, "text": "(let a 1)\n" }
+                }),
+            ))),
+            incoming(Message::Request(Request::new(
+                RequestId::from(2),
+                String::from("textDocument/documentSymbol"),
+                json!({ "textDocument": { "uri": uri } }),
+            ))),
+        ];
+
+        // #insight record the responses of a first replay, a second replay should match.
+        let replayed = replay(&entries).unwrap();
+        entries.extend(replayed.into_iter().map(|message| TraceEntry {
+            time: 0,
+            direction: Direction::Out,
+            message,
+        }));
+
+        let replayed = replay(&entries).unwrap();
+        assert!(diff_responses(&entries, &replayed).is_empty());
+
+        // #insight a response that differs from the recorded one.
+        let replayed: Vec<Message> = replayed
+            .into_iter()
+            .map(|message| match message {
+                Message::Response(mut resp) if resp.id == RequestId::from(2) => {
+                    resp.result = Some(json!([]));
+                    Message::Response(resp)
+                }
+                message => message,
+            })
+            .collect();
+        assert_eq!(diff_responses(&entries, &replayed).len(), 1);
+    }
+}
@@ -0,0 +1,140 @@
+use lsp_types::{Location, Position, Range, Uri};
+use tan::expr::Expr;
+
+use crate::{
+    definition::find_definitions,
+    util::{is_position_in_range, lsp_range_from_tan_range},
+};
+
+// #insight
+// References are resolved by name only, shadowing by local bindings (e.g.
+// function parameters) is not considered yet.
+
+/// Collects the ranges of all occurrences of the symbol `name`.
+pub fn find_symbol_occurrences(expr: &Expr, name: &str, ranges: &mut Vec<Range>) {
+    if let Some(terms) = expr.as_list() {
+        for term in terms {
+            find_symbol_occurrences(term, name, ranges);
+        }
+        return;
+    }
+
+    if expr.as_symbol() == Some(name) {
+        if let Some(range) = expr.range() {
+            ranges.push(lsp_range_from_tan_range(range));
+        }
+    }
+}
+
+/// Returns the symbol at the given position, and its range.
+pub fn symbol_at_position(exprs: &[Expr], position: Position) -> Option<(String, Range)> {
+    for expr in exprs {
+        if let Some(terms) = expr.as_list() {
+            if let Some(found) = symbol_at_position(terms, position) {
+                return Some(found);
+            }
+            continue;
+        }
+
+        let (Some(name), Some(range)) = (expr.as_symbol(), expr.range()) else {
+            continue;
+        };
+
+        let range = lsp_range_from_tan_range(range);
+        if is_position_in_range(position, range) {
+            return Some((name.to_string(), range));
+        }
+    }
+
+    None
+}
+
+/// Returns the references of the symbol at the given position, in all the
+/// workspace sources.
+pub fn compute_references(
+    uri: &Uri,
+    exprs: &[Expr],
+    position: Position,
+    sources: &[(Uri, &[Expr])],
+    include_declaration: bool,
+) -> Option<Vec<Location>> {
+    let (name, _) = symbol_at_position(exprs, position)?;
+
+    let definitions = find_definitions(uri, sources, &name);
+
+    let mut locations = Vec::new();
+    for (source_uri, source_exprs) in sources {
+        let mut ranges = Vec::new();
+        for expr in source_exprs.iter() {
+            find_symbol_occurrences(expr, &name, &mut ranges);
+        }
+        locations.extend(
+            ranges
+                .into_iter()
+                .map(|range| Location::new(source_uri.clone(), range)),
+        );
+    }
+
+    if !include_declaration {
+        locations.retain(|location| !definitions.contains(location));
+    }
+
+    Some(locations)
+}
+
+/// Returns the references of the symbol `name`, excluding the definition
+/// itself.
+pub fn find_references(exprs: &[Expr], name: &str, definition_range: Option<Range>) -> Vec<Range> {
+    let mut ranges = Vec::new();
+
+    for expr in exprs {
+        find_symbol_occurrences(expr, name, &mut ranges);
+    }
+
+    if let Some(definition_range) = definition_range {
+        ranges.retain(|range| *range != definition_range);
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use lsp_types::{Position, Uri};
+
+    use crate::{
+        references::{compute_references, find_references},
+        util::parse_string_all,
+    };
+
+    #[test]
+    fn find_references_usage() {
+        let input = r#"
+        (let a 1)
+        (let b (+ a 2))
+        (let c (* a b))
+        "#;
+
+        let exprs = parse_string_all(input).unwrap();
+        assert_eq!(find_references(&exprs, "a", None).len(), 3);
+
+        let definition_range = find_references(&exprs, "a", None).first().copied();
+        assert_eq!(find_references(&exprs, "a", definition_range).len(), 2);
+    }
+
+    #[test]
+    fn compute_references_usage() {
+        let uri = Uri::from_str("file:///project/main.tan").unwrap();
+        let input = "(let a 1)\n(let b (+ a 2))\n";
+        let exprs = parse_string_all(input).unwrap();
+        let sources = vec![(uri.clone(), exprs.as_slice())];
+
+        let locations = compute_references(&uri, &exprs, Position::new(1, 10), &sources, true);
+        assert_eq!(locations.unwrap().len(), 2);
+
+        let locations = compute_references(&uri, &exprs, Position::new(1, 10), &sources, false);
+        assert_eq!(locations.unwrap().len(), 1);
+    }
+}
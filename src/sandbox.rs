@@ -0,0 +1,311 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Command, ExitCode, Stdio},
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{unbounded, RecvTimeoutError, Sender};
+use lsp_types::{Position, Range};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    analysis::analyze_input_sync,
+    eval::{eval_sync, Cancellation, EvalTimeout},
+    expand_macro::expand_macro_sync,
+};
+
+// #insight
+// User code (analysis, macro expansion, evaluation) runs in a worker process,
+// the server binary with the hidden `sandbox-worker` subcommand. Tan has no
+// step budget, a runaway recursion or loop cannot be stopped in-process, and a
+// stack overflow aborts the process. The worker is killed on timeout or
+// cancellation instead.
+// #insight the job is written to the stdin of the worker, the worker writes
+// JSON lines to stdout: the output of the evaluated code, then the result.
+
+/// The hidden subcommand that runs a sandbox job, see `run_sandbox_worker`.
+pub const SANDBOX_WORKER_COMMAND: &str = "sandbox-worker";
+
+/// How often a running job checks the cancellation.
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A job for the worker process, expressions cannot cross the process
+/// boundary, the worker parses the input.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum SandboxJob {
+    Analyze {
+        input: String,
+        document_uri: String,
+        search_paths: Vec<PathBuf>,
+        sandbox: bool,
+    },
+    ExpandMacro {
+        input: String,
+        document_uri: String,
+        position: Position,
+        search_paths: Vec<PathBuf>,
+        sandbox: bool,
+    },
+    /// Evaluates the input, or the forms covered by the selection.
+    Eval {
+        input: String,
+        document_uri: String,
+        selection: Option<Range>,
+        sandbox: bool,
+    },
+}
+
+impl SandboxJob {
+    /// Runs the job in the worker, the output of the evaluated code is sent
+    /// to `output`.
+    fn run(self, output: Sender<String>) -> serde_json::Value {
+        match self {
+            SandboxJob::Analyze {
+                input,
+                document_uri,
+                search_paths,
+                sandbox,
+            } => serde_json::to_value(analyze_input_sync(
+                &input,
+                &document_uri,
+                &search_paths,
+                sandbox,
+            )),
+            SandboxJob::ExpandMacro {
+                input,
+                document_uri,
+                position,
+                search_paths,
+                sandbox,
+            } => serde_json::to_value(expand_macro_sync(
+                &input,
+                &document_uri,
+                position,
+                &search_paths,
+                sandbox,
+            )),
+            SandboxJob::Eval {
+                input,
+                document_uri,
+                selection,
+                sandbox,
+            } => serde_json::to_value(eval_sync(&input, &document_uri, selection, sandbox, output)),
+        }
+        .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "camelCase")]
+enum SandboxMessage {
+    /// A line of output of the evaluated code.
+    Output(String),
+    /// The result of the job.
+    Done(serde_json::Value),
+}
+
+fn write_message(out: &mut impl Write, message: &SandboxMessage) -> anyhow::Result<()> {
+    writeln!(out, "{}", serde_json::to_string(message)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Runs the job read from stdin, the entry point of the worker process.
+pub fn run_sandbox_worker() -> anyhow::Result<ExitCode> {
+    let job: SandboxJob = serde_json::from_reader(std::io::stdin().lock())?;
+
+    let (sender, receiver) = unbounded();
+    let worker = std::thread::spawn(move || job.run(sender));
+
+    let mut stdout = std::io::stdout().lock();
+
+    // #insight the output is streamed until the job drops the sender.
+    for line in receiver {
+        write_message(&mut stdout, &SandboxMessage::Output(line))?;
+    }
+
+    let Ok(value) = worker.join() else {
+        anyhow::bail!("the sandbox job panicked");
+    };
+    write_message(&mut stdout, &SandboxMessage::Done(value))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(test))]
+fn worker_command() -> std::io::Result<Command> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command.arg(SANDBOX_WORKER_COMMAND);
+    Ok(command)
+}
+
+/// Set in the worker processes of the tests, see `sandbox_worker`.
+#[cfg(test)]
+const TEST_WORKER_ENV: &str = "TAN_LS_TEST_SANDBOX_WORKER";
+
+// #insight the test binary has no `sandbox-worker` subcommand, the worker runs
+// in the `sandbox_worker` test instead.
+#[cfg(test)]
+fn worker_command() -> std::io::Result<Command> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args([
+            "sandbox::tests::sandbox_worker",
+            "--exact",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(TEST_WORKER_ENV, "1");
+    Ok(command)
+}
+
+/// Runs the job in a worker process, with a time limit. `on_output` is called
+/// for every line of output while the job is running. The worker is killed on
+/// timeout, and once `cancellation` is set. Returns `None` if the job was
+/// cancelled, or the worker failed, e.g. on a stack overflow.
+pub fn run_cancellable<T: DeserializeOwned>(
+    job: &SandboxJob,
+    timeout: Duration,
+    cancellation: &Cancellation,
+    mut on_output: impl FnMut(String),
+) -> Result<Option<T>, EvalTimeout> {
+    let child = worker_command().and_then(|mut command| {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+    });
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(error) => {
+            warn!("Cannot start the sandbox worker: {error}.");
+            return Ok(None);
+        }
+    };
+
+    // #insight stdin is closed once the job is written.
+    if let Some(stdin) = child.stdin.take() {
+        let _ = serde_json::to_writer(stdin, job);
+    }
+
+    let (sender, receiver) = unbounded();
+
+    if let Some(stdout) = child.stdout.take() {
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                // #insight other lines, e.g. of the test harness, are skipped.
+                if let Ok(message) = serde_json::from_str::<SandboxMessage>(&line) {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    let deadline = Instant::now() + timeout;
+
+    let result = loop {
+        if cancellation.is_cancelled() {
+            break Ok(None);
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            cancellation.cancel();
+            break Err(EvalTimeout);
+        }
+
+        match receiver.recv_timeout(remaining.min(CANCELLATION_POLL_INTERVAL)) {
+            Ok(SandboxMessage::Output(line)) => on_output(line),
+            Ok(SandboxMessage::Done(value)) => break Ok(serde_json::from_value(value).ok()),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break Ok(None),
+        }
+    };
+
+    // #insight the worker is killed if still running, and reaped in any case.
+    let _ = child.kill();
+    let _ = child.wait();
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        eval::Cancellation,
+        sandbox::{run_cancellable, run_sandbox_worker, SandboxJob, TEST_WORKER_ENV},
+    };
+
+    #[test]
+    fn sandbox_worker() {
+        // #insight a no-op, unless spawned as a worker, see `worker_command`.
+        if std::env::var_os(TEST_WORKER_ENV).is_some() {
+            let code = if run_sandbox_worker().is_ok() { 0 } else { 1 };
+            std::process::exit(code);
+        }
+    }
+
+    fn eval_job(input: &str) -> SandboxJob {
+        SandboxJob::Eval {
+            input: input.to_string(),
+            document_uri: String::from("file:///project/main.tan"),
+            selection: None,
+            sandbox: true,
+        }
+    }
+
+    #[test]
+    fn run_cancellable_usage() {
+        let mut output = Vec::new();
+        let result: Option<Result<String, Vec<String>>> = run_cancellable(
+            &eval_job("(writeln \"hello\")\n(+ 1 2)"),
+            Duration::from_secs(10),
+            &Cancellation::default(),
+            |line| output.push(line),
+        )
+        .unwrap();
+        assert_eq!(result, Some(Ok(String::from("3"))));
+        assert_eq!(output, vec![String::from("hello")]);
+    }
+
+    #[test]
+    fn run_cancellable_kills_runaway_jobs() {
+        // #insight pure recursion calls no foreign functions, only the kill stops it.
+        let job = eval_job("(let loop-forever (Func [] (loop-forever)))\n(loop-forever)");
+
+        let cancellation = Cancellation::default();
+        let start = Instant::now();
+        let result = run_cancellable::<Result<String, Vec<String>>>(
+            &job,
+            Duration::from_millis(500),
+            &cancellation,
+            |_| {},
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // #insight timed out, or the worker overflowed its stack, the server survives.
+        assert!(!matches!(result, Ok(Some(Ok(_)))));
+
+        // #insight a cancelled job is killed before the timeout.
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+        let result = run_cancellable::<Result<String, Vec<String>>>(
+            &job,
+            Duration::from_secs(60),
+            &cancellation,
+            |_| {},
+        );
+        assert!(matches!(result, Ok(None)));
+    }
+}
@@ -1,167 +1,1670 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use crossbeam::select;
+use lsp_server::{Connection, Message, Response};
+use lsp_types::{
+    notification::{
+        DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles, DidCloseTextDocument,
+        DidOpenTextDocument, Exit, Notification, PublishDiagnostics, WorkDoneProgressCancel,
+    },
+    request::{
+        CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare,
+        CodeLensRequest, Completion, DocumentDiagnosticRequest, DocumentLinkRequest,
+        DocumentSymbolRequest, ExecuteCommand, Formatting, GotoDefinition, InlayHintRequest,
+        InlayHintResolveRequest, References, RegisterCapability, Request, Shutdown,
+        WorkspaceConfiguration, WorkspaceDiagnosticRefresh,
+    },
+    CallHierarchyIncomingCallsParams, CallHierarchyOptions, CallHierarchyOutgoingCallsParams,
+    CallHierarchyPrepareParams, CallHierarchyServerCapability, ClientCapabilities, CodeLensOptions,
+    CodeLensParams, CompletionOptions, CompletionParams, CompletionResponse, ConfigurationItem,
+    ConfigurationParams, DefinitionOptions, Diagnostic, DiagnosticOptions,
+    DiagnosticServerCapabilities, DidChangeConfigurationParams, DidChangeTextDocumentParams,
+    DidChangeWatchedFilesParams, DidChangeWatchedFilesRegistrationOptions,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentDiagnosticParams,
+    DocumentDiagnosticReport, DocumentDiagnosticReportResult, DocumentFormattingOptions,
+    DocumentFormattingParams, DocumentLinkOptions, DocumentLinkParams, DocumentSymbolParams,
+    DocumentSymbolResponse, ExecuteCommandOptions, ExecuteCommandParams, FileSystemWatcher,
+    FullDocumentDiagnosticReport, GlobPattern, GotoDefinitionParams, InitializeResult, InlayHint,
+    InlayHintOptions, InlayHintParams, InlayHintServerCapabilities, MessageType, OneOf,
+    ProgressToken, PublishDiagnosticsParams, Range, ReferenceParams, ReferencesOptions,
+    Registration, RegistrationParams, RelatedFullDocumentDiagnosticReport, ServerCapabilities,
+    ServerInfo, TextDocumentPositionParams, TextDocumentSyncKind, TextEdit, Uri,
+    WorkDoneProgressCancelParams, WorkDoneProgressOptions,
+};
+use tan::{error::Error, expr::Expr};
+use tracing::{info, trace, warn};
+
+use crate::{
+    analysis::{AnalysisQueue, AnalysisResult, AnalyzedBinding},
+    call_hierarchy::{incoming_calls, outgoing_calls, prepare_call_hierarchy, CallGraph},
+    capabilities::NegotiatedCapabilities,
+    code_lens::{compute_code_lenses, is_test_func_defined, RUN_COMMAND, TEST_COMMAND},
+    completion::compute_completions,
+    config::{settings_section, ServerConfig, CONFIGURATION_SECTION},
+    definition::compute_definition,
+    diagnostics::{analysis_diagnostics, compute_static_diagnostics},
+    eval::{
+        eval_sandboxed, eval_selection_sandboxed, select_forms, Cancellation, EvaluationResult,
+        EVALUATE_SELECTION_COMMAND,
+    },
+    expand_macro::{expand_macro, ExpandMacro},
+    formatting::format_exprs,
+    imports::{compute_document_links, imported_bindings},
+    inlay_hints::{compute_inlay_hints, resolve_inlay_hint},
+    lifecycle::{spawn_parent_process_watchdog, ShutdownStatus},
+    line_index::{ConversionDirection, ConvertPositions, LineIndex, PositionConverter},
+    parse_cache::ParsedDocument,
+    progress::{percentage, WorkDoneProgressReporter, LARGE_DOCUMENT_LINES},
+    project_config::{
+        is_project_config_uri, merge_json, parse_project_config, read_project_config,
+        PROJECT_CONFIG_FILE_NAME,
+    },
+    record::record_connection,
+    references::compute_references,
+    status::{send_server_status_notification, ServerState, ServerStatus},
+    symbols::compute_document_symbol_response,
+    syntax_tree::{compute_syntax_tree, SyntaxTree, SyntaxTreeParams},
+    transport::Transport,
+    util::{
+        document_module_path, lsp_range_whole_document, make_analysis_context, parse_string_all,
+        path_from_uri, send_log_message_notification, VERSION,
+    },
+    workspace::WorkspaceIndex,
+};
+
+const IO_THREADS_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the server waits for the response to its requests, e.g.
+/// `window/workDoneProgress/create`.
+const SERVER_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The requests that report progress with the `workDoneToken`.
+const WORK_DONE_PROGRESS_OPTIONS: WorkDoneProgressOptions = WorkDoneProgressOptions {
+    work_done_progress: Some(true),
+};
+
+// #insight
+// For debugging use trace! and similar functions, the traces are logged in the
+// `Tan Language` tab of the Output panel, in VS Code.
+
+pub struct Server {
+    documents: HashMap<String, String>,
+    /// The parsed open documents, re-parsed incrementally on change.
+    parsed_documents: HashMap<String, ParsedDocument>,
+    /// The line indexes of the open documents, for the position conversions.
+    line_indexes: HashMap<String, LineIndex<'static>>,
+    /// The parsed files of the workspace.
+    workspace_index: WorkspaceIndex,
+    /// The workspace index is updated by the next workspace request, set
+    /// initially, on cancellation and when the configuration changes.
+    workspace_index_stale: bool,
+    /// The background analyses of the open documents.
+    analysis: AnalysisQueue,
+    /// The diagnostics of the latest analysis, per document.
+    analysis_diagnostics: HashMap<String, Vec<Diagnostic>>,
+    /// The bindings of the latest analysis, per document, for the outline.
+    analysis_bindings: HashMap<String, Vec<AnalyzedBinding>>,
+    // #todo also cache 'parsed/compiled' documents -> partial modules.
+    config: ServerConfig,
+    /// The settings from `initializationOptions` or `workspace/configuration`.
+    editor_settings: serde_json::Value,
+    /// The settings from the project configuration file.
+    project_settings: serde_json::Value,
+    /// The capabilities negotiated with the client, on initialization.
+    capabilities: NegotiatedCapabilities,
+    configuration_request_id: Option<lsp_server::RequestId>,
+    next_request_id: i32,
+    workspace_root: Option<PathBuf>,
+    /// Exit if the parent (editor) process dies, disabled for in-memory
+    /// connections, e.g. in tests and replays.
+    watch_parent_process: bool,
+    shutdown_requested: bool,
+    status: ServerStatus,
+    /// The diagnostics count per document, for the status.
+    diagnostic_counts: HashMap<String, usize>,
+    /// The error of the project configuration file, if invalid.
+    project_config_error: Option<String>,
+    /// The error of the merged settings, if any field is invalid.
+    settings_error: Option<String>,
+    /// Messages received while polling for cancellations, during long
+    /// operations.
+    pending_messages: VecDeque<Message>,
+    /// The tokens of the work-done progress cancelled by the client.
+    cancelled_progress: Vec<ProgressToken>,
+}
+
+// #todo split further into methods.
+
+impl Server {
+    pub fn new() -> Self {
+        Self {
+            documents: HashMap::default(),
+            parsed_documents: HashMap::default(),
+            line_indexes: HashMap::default(),
+            workspace_index: WorkspaceIndex::default(),
+            workspace_index_stale: true,
+            analysis: AnalysisQueue::default(),
+            analysis_diagnostics: HashMap::default(),
+            analysis_bindings: HashMap::default(),
+            config: ServerConfig::default(),
+            editor_settings: serde_json::Value::Null,
+            project_settings: serde_json::Value::Null,
+            capabilities: NegotiatedCapabilities::default(),
+            configuration_request_id: None,
+            next_request_id: 0,
+            workspace_root: None,
+            watch_parent_process: false,
+            shutdown_requested: false,
+            status: ServerStatus::default(),
+            diagnostic_counts: HashMap::default(),
+            project_config_error: None,
+            settings_error: None,
+            pending_messages: VecDeque::default(),
+            cancelled_progress: Vec::new(),
+        }
+    }
+
+    pub fn run(
+        &mut self,
+        transport: &Transport,
+        record: Option<&Path>,
+    ) -> anyhow::Result<ShutdownStatus> {
+        info!(
+            "Starting LSP server, v{}, transport: {:?}...",
+            VERSION, transport
+        );
+
+        let (connection, io_threads) = transport.connect()?;
+
+        self.watch_parent_process = true;
+
+        let status = if let Some(path) = record {
+            info!("Recording the session to `{}`.", path.display());
+            let (connection, recorder) = record_connection(connection, path)?;
+            let status = self.serve(connection)?;
+            // #insight the recording connection is dropped, wait for the outgoing messages.
+            let _ = recorder.join();
+            status
+        } else {
+            self.serve(connection)?
+        };
+
+        // Wait for the two threads to end (typically by trigger LSP Exit event).
+        // #insight a misbehaving client may keep the connection open, don't wait forever.
+        if io_threads.join_timeout(IO_THREADS_JOIN_TIMEOUT).is_err() {
+            warn!("The connection was not closed, exiting anyway.");
+        }
+
+        info!("Shutting down server, {:?}...", status);
+
+        Ok(status)
+    }
+
+    /// The capabilities of the server, adapted to the capabilities of the
+    /// client.
+    pub fn server_capabilities(&self) -> ServerCapabilities {
+        ServerCapabilities {
+            position_encoding: Some(self.capabilities.position_encoding.clone()),
+            definition_provider: Some(OneOf::Right(DefinitionOptions {
+                work_done_progress_options: WORK_DONE_PROGRESS_OPTIONS,
+            })),
+            references_provider: Some(OneOf::Right(ReferencesOptions {
+                work_done_progress_options: WORK_DONE_PROGRESS_OPTIONS,
+            })),
+            // #insight Enables didOpen/didChange notifications.
+            document_symbol_provider: Some(OneOf::Left(true)),
+            text_document_sync: Some(lsp_types::TextDocumentSyncCapability::Kind(
+                TextDocumentSyncKind::FULL,
+            )),
+            rename_provider: Some(OneOf::Left(true)),
+            document_formatting_provider: Some(OneOf::Right(DocumentFormattingOptions {
+                work_done_progress_options: WORK_DONE_PROGRESS_OPTIONS,
+            })),
+            document_link_provider: Some(DocumentLinkOptions {
+                resolve_provider: Some(false),
+                work_done_progress_options: Default::default(),
+            }),
+            inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(
+                InlayHintOptions {
+                    resolve_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                },
+            ))),
+            call_hierarchy_provider: Some(CallHierarchyServerCapability::Options(
+                CallHierarchyOptions {
+                    work_done_progress_options: WORK_DONE_PROGRESS_OPTIONS,
+                },
+            )),
+            code_lens_provider: Some(CodeLensOptions {
+                resolve_provider: Some(false),
+            }),
+            completion_provider: Some(CompletionOptions::default()),
+            execute_command_provider: Some(ExecuteCommandOptions {
+                commands: vec![
+                    RUN_COMMAND.to_string(),
+                    TEST_COMMAND.to_string(),
+                    EVALUATE_SELECTION_COMMAND.to_string(),
+                ],
+                work_done_progress_options: Default::default(),
+            }),
+            diagnostic_provider: self.capabilities.pull_diagnostics.then(|| {
+                DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                    identifier: Some(String::from("tan")),
+                    // #insight imports affect the diagnostics of a document.
+                    inter_file_dependencies: true,
+                    workspace_diagnostics: false,
+                    work_done_progress_options: Default::default(),
+                })
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Initializes the connection and runs the server, until exit.
+    pub fn serve(&mut self, connection: Connection) -> anyhow::Result<ShutdownStatus> {
+        let (id, initialization_params) = connection.initialize_start()?;
+
+        let client_capabilities = initialization_params
+            .get("capabilities")
+            .and_then(|capabilities| {
+                serde_json::from_value::<ClientCapabilities>(capabilities.clone()).ok()
+            })
+            .unwrap_or_default();
+        self.capabilities = NegotiatedCapabilities::from_client(&client_capabilities);
+
+        info!("Negotiated capabilities: {:?}.", self.capabilities);
+
+        let result = InitializeResult {
+            capabilities: self.server_capabilities(),
+            server_info: Some(ServerInfo {
+                name: String::from("tan-language-server"),
+                version: Some(VERSION.to_string()),
+            }),
+        };
+        connection.initialize_finish(id, serde_json::to_value(result)?)?;
+
+        info!("Started.");
+        self.publish_status(&connection, "started")?;
+
+        // Run the server.
+        self.run_loop(connection, initialization_params)
+    }
+
+    /// Publishes the status, with the current counts.
+    pub fn publish_status(&self, connection: &Connection, text: &str) -> anyhow::Result<()> {
+        let status = ServerStatus {
+            indexed_files: self.workspace_index.file_count(),
+            diagnostics: self.diagnostic_counts.values().sum(),
+            ..self.status.clone()
+        };
+        send_server_status_notification(connection, text, &status)?;
+        Ok(())
+    }
+
+    /// Sets the state, and publishes the status.
+    pub fn set_state(
+        &mut self,
+        connection: &Connection,
+        state: ServerState,
+        progress: Option<u32>,
+    ) -> anyhow::Result<()> {
+        self.status.state = state;
+        self.status.progress = progress;
+        self.publish_status(connection, state.as_str())
+    }
+
+    /// The state when no operation is in progress.
+    pub fn idle_state(&self) -> ServerState {
+        if self.project_config_error.is_some() {
+            ServerState::Error
+        } else {
+            ServerState::Ready
+        }
+    }
+
+    /// Records the diagnostics count of a document, the status is published
+    /// if the total changed, unless an operation is in progress.
+    fn record_diagnostics(
+        &mut self,
+        connection: &Connection,
+        uri: &Uri,
+        count: usize,
+    ) -> anyhow::Result<()> {
+        let previous = self.diagnostic_counts.insert(uri.to_string(), count);
+
+        if previous != Some(count) && self.status.state == self.idle_state() {
+            self.publish_status(connection, self.status.state.as_str())?;
+        }
+
+        Ok(())
+    }
+
+    // #todo find a good name.
+    pub fn process_document(&mut self, uri: &Uri, text: &str) {
+        let input = text.to_string();
+        let uri = uri.to_string();
+
+        if let Some(document) = self.parsed_documents.get_mut(&uri) {
+            let reparsed = document.update(&input);
+            trace!("Re-parsed {reparsed} chunks of {uri}.");
+        } else {
+            self.parsed_documents
+                .insert(uri.clone(), ParsedDocument::new(&input));
+        }
+
+        // #insight the ranges of the previous analysis are stale.
+        self.analysis_diagnostics.remove(&uri);
+        self.analysis_bindings.remove(&uri);
+
+        self.line_indexes
+            .insert(uri.clone(), LineIndex::new(input.clone()));
+        self.documents.insert(uri.clone(), input);
+
+        self.schedule_analysis(&uri);
+    }
+
+    /// Handles `didClose`, drops the document and cancels its analysis. The
+    /// published diagnostics of the document are cleared.
+    pub fn close_document(&mut self, connection: &Connection, uri: Uri) -> anyhow::Result<()> {
+        let key = uri.as_str();
+
+        self.analysis.cancel(key);
+        self.analysis_diagnostics.remove(key);
+        self.analysis_bindings.remove(key);
+        self.documents.remove(key);
+        self.parsed_documents.remove(key);
+        self.line_indexes.remove(key);
+        self.diagnostic_counts.remove(key);
+
+        if !self.capabilities.pull_diagnostics {
+            let pdm = PublishDiagnosticsParams {
+                uri,
+                diagnostics: Vec::new(),
+                version: None,
+            };
+
+            let notification = lsp_server::Notification {
+                method: PublishDiagnostics::METHOD.to_owned(),
+                params: serde_json::to_value(pdm).unwrap(),
+            };
+
+            connection
+                .sender
+                .send(Message::Notification(notification))?;
+        }
+
+        self.set_state(connection, self.idle_state(), None)
+    }
+
+    /// Schedules the background analysis of an open document, documents with
+    /// parse errors are not analyzed.
+    pub fn schedule_analysis(&mut self, uri: &str) {
+        let search_paths = self.module_search_paths();
+
+        // #insight the worker parses the input again, the expressions cannot
+        // cross the process boundary, the cached parse only gates the analysis.
+        let (Some(Ok(_)), Some(input)) = (self.parse_result(uri), self.documents.get(uri)) else {
+            self.analysis.cancel(uri);
+            return;
+        };
+
+        self.analysis.schedule(
+            input.clone(),
+            uri.to_string(),
+            search_paths,
+            &self.config.eval,
+        );
+    }
+
+    /// Handles the result of a background analysis. The bindings are kept for
+    /// the outline, the diagnostics are re-published, or refreshed for pull
+    /// clients, if the analysis reports any.
+    fn handle_analysis_result(
+        &mut self,
+        connection: &Connection,
+        mut result: AnalysisResult,
+    ) -> anyhow::Result<()> {
+        if !self.analysis.complete(&result) || !self.documents.contains_key(&result.uri) {
+            trace!("Dropped the superseded analysis of {}.", result.uri);
+            return Ok(());
+        }
+
+        if let Ok(analysis) = &mut result.analysis {
+            self.analysis_bindings
+                .insert(result.uri.clone(), std::mem::take(&mut analysis.bindings));
+        }
+
+        self.publish_status(connection, "analyzed")?;
+
+        let diagnostics = analysis_diagnostics(result.analysis, &self.config.eval);
+
+        // #insight the other diagnostics are already published.
+        if diagnostics.is_empty() {
+            return Ok(());
+        }
+
+        self.analysis_diagnostics
+            .insert(result.uri.clone(), diagnostics);
+
+        if self.capabilities.pull_diagnostics {
+            return self.request_diagnostic_refresh(connection);
+        }
+
+        if let Ok(uri) = Uri::from_str(&result.uri) {
+            self.send_diagnostics(connection, uri)?;
+        }
+
+        Ok(())
+    }
+
+    /// Asks the client to pull the diagnostics again, if supported.
+    fn request_diagnostic_refresh(&mut self, connection: &Connection) -> anyhow::Result<()> {
+        if !self.capabilities.diagnostic_refresh {
+            return Ok(());
+        }
+
+        self.next_request_id += 1;
+        let req = lsp_server::Request::new(
+            lsp_server::RequestId::from(self.next_request_id),
+            WorkspaceDiagnosticRefresh::METHOD.to_owned(),
+            (),
+        );
+        connection.sender.send(Message::Request(req))?;
+
+        Ok(())
+    }
+
+    /// The (cached) parse result of an open document.
+    pub fn parse_result(&self, uri: &str) -> Option<&Result<Vec<Expr>, Vec<Error>>> {
+        self.parsed_documents.get(uri).map(ParsedDocument::result)
+    }
+
+    /// Converts the (character) positions of a value to the negotiated
+    /// encoding, for the client.
+    pub fn encode_positions<T: ConvertPositions>(&self, uri: &str, mut value: T) -> T {
+        let mut converter = PositionConverter::new(
+            self.capabilities.position_encoding.clone(),
+            ConversionDirection::ToClient,
+            &self.line_indexes,
+        );
+        value.convert_positions(uri, &mut converter);
+        value
+    }
+
+    /// Converts the positions of a value from the client to (character)
+    /// positions.
+    pub fn decode_positions<T: ConvertPositions>(&self, uri: &str, mut value: T) -> T {
+        let mut converter = PositionConverter::new(
+            self.capabilities.position_encoding.clone(),
+            ConversionDirection::FromClient,
+            &self.line_indexes,
+        );
+        value.convert_positions(uri, &mut converter);
+        value
+    }
+
+    pub fn document_diagnostics(&self, uri: &Uri) -> anyhow::Result<Vec<Diagnostic>> {
+        let Some(parse_result) = self.parse_result(uri.as_str()) else {
+            return Err(anyhow!("invalid document").context("in document_diagnostics"));
+        };
+
+        let mut diagnostics = compute_static_diagnostics(
+            uri.as_str(),
+            parse_result,
+            &self.config,
+            &self.module_search_paths(),
+        )?;
+
+        // #insight the analysis diagnostics are computed in the background.
+        if let Some(analysis_diagnostics) = self.analysis_diagnostics.get(uri.as_str()) {
+            diagnostics.extend(analysis_diagnostics.iter().cloned());
+        }
+
+        Ok(self.encode_positions(uri.as_str(), diagnostics))
+    }
+
+    // #todo return a more precise result.
+    pub fn send_diagnostics(&mut self, connection: &Connection, uri: Uri) -> anyhow::Result<()> {
+        // #insight the client pulls the diagnostics.
+        if self.capabilities.pull_diagnostics {
+            return Ok(());
+        }
+
+        let diagnostics = self.document_diagnostics(&uri)?;
+        self.record_diagnostics(connection, &uri, diagnostics.len())?;
+
+        let pdm = PublishDiagnosticsParams {
+            uri: uri.clone(),
+            diagnostics,
+            version: None,
+        };
+
+        let notification = lsp_server::Notification {
+            method: PublishDiagnostics::METHOD.to_owned(),
+            params: serde_json::to_value(pdm).unwrap(),
+        };
+
+        connection
+            .sender
+            .send(Message::Notification(notification))?;
+
+        Ok(())
+    }
+
+    pub fn handle_document_diagnostic(
+        &mut self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) =
+            req.extract::<DocumentDiagnosticParams>(DocumentDiagnosticRequest::METHOD)?;
+
+        // #insight unknown documents have no diagnostics.
+        let items = self
+            .document_diagnostics(&params.text_document.uri)
+            .unwrap_or_default();
+        self.record_diagnostics(connection, &params.text_document.uri, items.len())?;
+
+        let result = DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(
+            RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: None,
+                    items,
+                },
+            },
+        ));
+
+        let result = serde_json::to_value(result).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_syntax_tree(
+        &self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<SyntaxTreeParams>(SyntaxTree::METHOD)?;
+
+        let uri = params.text_document.uri.as_str();
+
+        let result = self.documents.get(uri).map(|input| {
+            let position = params
+                .position
+                .map(|position| self.decode_positions(uri, position));
+            self.encode_positions(uri, compute_syntax_tree(input, position))
+        });
+
+        let result = serde_json::to_value(result).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_expand_macro(
+        &self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<TextDocumentPositionParams>(ExpandMacro::METHOD)?;
+
+        let uri = params.text_document.uri.as_str();
+
+        // #insight documents with parse errors are not expanded.
+        let (Some(Ok(_)), Some(input)) = (self.parse_result(uri), self.documents.get(uri)) else {
+            connection.sender.send(Message::Response(Response::new_ok(
+                id,
+                serde_json::Value::Null,
+            )))?;
+            return Ok(());
+        };
+
+        self.publish_status(connection, "expanding")?;
+
+        let resp = match expand_macro(
+            input.clone(),
+            uri.to_string(),
+            self.decode_positions(uri, params.position),
+            self.module_search_paths(),
+            &self.config.eval,
+            &Cancellation::default(),
+        ) {
+            Ok(expansion) => Response::new_ok(id, self.encode_positions(uri, expansion)),
+            Err(_) => Response::new_err(
+                id,
+                lsp_server::ErrorCode::RequestFailed as i32,
+                format!(
+                    "expansion timed out after {:?}",
+                    self.config.eval.analysis_timeout()
+                ),
+            ),
+        };
+
+        self.publish_status(connection, "expanded")?;
+
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_document_link(
+        &self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<DocumentLinkParams>(DocumentLinkRequest::METHOD)?;
+
+        let links = if let Some(Ok(exprs)) = self.parse_result(params.text_document.uri.as_str()) {
+            let module_path = document_module_path(params.text_document.uri.as_str())?;
+            let links = compute_document_links(exprs, &module_path, &self.module_search_paths());
+            self.encode_positions(params.text_document.uri.as_str(), links)
+        } else {
+            Vec::new()
+        };
+
+        let result = serde_json::to_value(Some(links)).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_inlay_hint(
+        &self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<InlayHintParams>(InlayHintRequest::METHOD)?;
+
+        let uri = params.text_document.uri.as_str();
+
+        let hints = if let Some(Ok(exprs)) = self.parse_result(uri) {
+            let analysis_context = make_analysis_context(uri)?;
+            let hints = compute_inlay_hints(
+                exprs,
+                &analysis_context,
+                self.decode_positions(uri, params.range),
+                &self.config.inlay_hints,
+            );
+            self.encode_positions(uri, hints)
+        } else {
+            Vec::new()
+        };
+
+        let result = serde_json::to_value(Some(hints)).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_inlay_hint_resolve(
+        &self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, hint) = req.extract::<InlayHint>(InlayHintResolveRequest::METHOD)?;
+
+        let result =
+            serde_json::to_value(resolve_inlay_hint(hint, self.capabilities.markdown)).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    /// Updates the workspace index, only new and modified files are parsed.
+    /// The walk is reported with the `workDoneToken` of the request, or with
+    /// a server-initiated (cancellable) progress. The status is published
+    /// once the index is updated.
+    pub fn update_workspace_index(
+        &mut self,
+        connection: &Connection,
+        token: Option<ProgressToken>,
+    ) -> anyhow::Result<()> {
+        let Some(root) = self.workspace_root.clone() else {
+            return Ok(());
+        };
+        let config = self.config.workspace.clone();
+
+        let progress = self.start_progress(connection, token, "Indexing", true)?;
+        self.set_state(connection, ServerState::Indexing, Some(0))?;
+
+        // #insight the index is taken out, the walk polls the connection for
+        // cancellations.
+        let mut index = std::mem::take(&mut self.workspace_index);
+        let mut last_percentage = 0;
+        let mut cancelled = false;
+
+        index.update(Some(&root), &config, |done, total| {
+            let Some(progress) = &progress else {
+                return ControlFlow::Continue(());
+            };
+
+            // #insight the remaining files are indexed by the next update.
+            if self.is_progress_cancelled(connection, progress) {
+                cancelled = true;
+                return ControlFlow::Break(());
+            }
+
+            // #insight report only when the percentage changes.
+            let percentage = percentage(done, total);
+            if percentage != last_percentage {
+                last_percentage = percentage;
+                let _ = progress.report(&format!("{done}/{total} files"), percentage);
+                let _ = self.set_state(connection, ServerState::Indexing, Some(percentage));
+            }
+            ControlFlow::Continue(())
+        });
+
+        self.workspace_index = index;
+        self.workspace_index_stale = cancelled;
+
+        if let Some(progress) = progress {
+            self.end_progress(progress, if cancelled { "cancelled" } else { "indexed" })?;
+        }
+
+        self.set_state(connection, self.idle_state(), None)
+    }
+
+    /// Updates the workspace index before a workspace request, if it is stale.
+    /// The watched files keep the index up to date, without file watchers the
+    /// index is updated on every request.
+    pub fn ensure_workspace_index(
+        &mut self,
+        connection: &Connection,
+        token: Option<ProgressToken>,
+    ) -> anyhow::Result<()> {
+        if self.workspace_index_stale || !self.capabilities.watched_files_registration {
+            self.update_workspace_index(connection, token)?;
+        }
+        Ok(())
+    }
+
+    /// The parsed sources of the workspace, open documents take precedence
+    /// over the files on disk. Call `ensure_workspace_index` first.
+    pub fn workspace_sources(&self) -> Vec<(Uri, &[Expr])> {
+        self.workspace_index.sources(&self.parsed_documents)
+    }
+
+    /// Starts a work-done progress, with the `workDoneToken` of the request or
+    /// with a server-initiated token, if the client supports it. The
+    /// server-initiated progress begins once the client created the token.
+    pub fn start_progress<'c>(
+        &mut self,
+        connection: &'c Connection,
+        token: Option<ProgressToken>,
+        title: &str,
+        cancellable: bool,
+    ) -> anyhow::Result<Option<WorkDoneProgressReporter<'c>>> {
+        let progress = match token {
+            // #insight requests are cancelled with `$/cancelRequest` instead.
+            Some(token) => {
+                let progress = WorkDoneProgressReporter::with_token(connection, token);
+                progress.begin(title, false)?;
+                return Ok(Some(progress));
+            }
+            None if self.capabilities.work_done_progress => {
+                self.next_request_id += 1;
+                let id = self.next_request_id;
+                let progress = WorkDoneProgressReporter::create(connection, id)?;
+                if !self.wait_for_response(connection, &lsp_server::RequestId::from(id)) {
+                    return Ok(None);
+                }
+                progress
+            }
+            None => return Ok(None),
+        };
+
+        progress.begin(title, cancellable)?;
+
+        Ok(Some(progress))
+    }
+
+    /// Ends a work-done progress, and forgets its cancellation.
+    pub fn end_progress(
+        &mut self,
+        progress: WorkDoneProgressReporter,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        self.cancelled_progress
+            .retain(|token| token != progress.token());
+        progress.end(message)?;
+        Ok(())
+    }
+
+    /// Keeps a message received during a long operation, the cancellations
+    /// of work-done progress are recorded, other messages are handled after
+    /// the operation.
+    fn defer_message(&mut self, msg: Message) {
+        match msg {
+            Message::Notification(notification)
+                if notification.method == WorkDoneProgressCancel::METHOD =>
+            {
+                if let Ok(params) = notification
+                    .extract::<WorkDoneProgressCancelParams>(WorkDoneProgressCancel::METHOD)
+                {
+                    self.cancelled_progress.push(params.token);
+                }
+            }
+            msg => self.pending_messages.push_back(msg),
+        }
+    }
+
+    /// Waits for the response to a server request, e.g.
+    /// `window/workDoneProgress/create`. Returns false if the request failed,
+    /// a client that does not respond in time is not waited for.
+    fn wait_for_response(&mut self, connection: &Connection, id: &lsp_server::RequestId) -> bool {
+        let deadline = Instant::now() + SERVER_REQUEST_TIMEOUT;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match connection.receiver.recv_timeout(remaining) {
+                Ok(Message::Response(resp)) if &resp.id == id => return resp.error.is_none(),
+                Ok(msg) => self.defer_message(msg),
+                Err(_) => {
+                    warn!("No response to the server request {id}.");
+                    return true;
+                }
+            }
+        }
+    }
+
+    /// Polls the connection for `window/workDoneProgress/cancel`, other
+    /// messages are handled after the current operation.
+    pub fn is_progress_cancelled(
+        &mut self,
+        connection: &Connection,
+        progress: &WorkDoneProgressReporter,
+    ) -> bool {
+        while let Ok(msg) = connection.receiver.try_recv() {
+            self.defer_message(msg);
+        }
+
+        self.cancelled_progress.contains(progress.token())
+    }
+
+    /// The next message, the pending messages are handled first. The results
+    /// of the background analyses are handled while waiting.
+    fn next_message(&mut self, connection: &Connection) -> anyhow::Result<Option<Message>> {
+        loop {
+            if let Some(msg) = self.pending_messages.pop_front() {
+                return Ok(Some(msg));
+            }
+
+            let analysis_receiver = self.analysis.receiver().clone();
+
+            select! {
+                recv(connection.receiver) -> msg => return Ok(msg.ok()),
+                recv(analysis_receiver) -> result => {
+                    if let Ok(result) = result {
+                        self.handle_analysis_result(connection, result)?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Formats the cached parse result of a document. Documents with parse
+    /// errors cannot be formatted, the request fails.
+    pub fn handle_formatting(
+        &mut self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<DocumentFormattingParams>(Formatting::METHOD)?;
+
+        let uri = params.text_document.uri.as_str();
+
+        match self.parse_result(uri) {
+            Some(Ok(_)) => {}
+            Some(Err(_)) => {
+                connection.sender.send(Message::Response(Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::RequestFailed as i32,
+                    String::from("cannot format, the document has parse errors"),
+                )))?;
+                return Ok(());
+            }
+            None => {
+                // #insight unknown documents are not formatted.
+                connection.sender.send(Message::Response(Response::new_ok(
+                    id,
+                    serde_json::Value::Null,
+                )))?;
+                return Ok(());
+            }
+        }
+
+        self.publish_status(connection, "formatting")?;
+
+        // #insight only large documents report progress, unless requested.
+        let token = params.work_done_progress_params.work_done_token;
+        let is_large = self
+            .documents
+            .get(uri)
+            .is_some_and(|input| input.lines().count() > LARGE_DOCUMENT_LINES);
+        let progress = if token.is_some() || is_large {
+            self.start_progress(connection, token, "Formatting", false)?
+        } else {
+            None
+        };
+
+        // #insight the cached parse result is formatted.
+        let Some(Ok(exprs)) = self.parse_result(uri) else {
+            unreachable!("the document is parsed");
+        };
+
+        if let Some(progress) = &progress {
+            progress.report(&format!("{} expressions", exprs.len()), 0)?;
+        }
+
+        let formatted = format_exprs(exprs, self.config.dialect_for(uri));
+
+        // #todo does it make sense to compute diffs?
+
+        // Select the whole document for replacement
+        let result = Some(vec![TextEdit::new(lsp_range_whole_document(), formatted)]);
+        let result = serde_json::to_value(&result).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+
+        if let Some(progress) = progress {
+            self.end_progress(progress, "formatted")?;
+        }
+
+        self.publish_status(connection, "formatted")?;
+
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_goto_definition(
+        &mut self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<GotoDefinitionParams>(GotoDefinition::METHOD)?;
+
+        let token = params.work_done_progress_params.work_done_token;
+        let uri = params.text_document_position_params.text_document.uri;
+        let position =
+            self.decode_positions(uri.as_str(), params.text_document_position_params.position);
+
+        self.ensure_workspace_index(connection, token)?;
+
+        let response = if let Some(Ok(exprs)) = self.parse_result(uri.as_str()) {
+            let sources = self.workspace_sources();
+            let module_path = document_module_path(uri.as_str())?;
+            let imports = imported_bindings(exprs, &module_path, &self.module_search_paths());
+            let response = compute_definition(&uri, exprs, position, &sources, &imports);
+            self.encode_positions(uri.as_str(), response)
+        } else {
+            None
+        };
+
+        let result = serde_json::to_value(response).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_references(
+        &mut self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<ReferenceParams>(References::METHOD)?;
+
+        let token = params.work_done_progress_params.work_done_token;
+        let uri = params.text_document_position.text_document.uri;
+        let position = self.decode_positions(uri.as_str(), params.text_document_position.position);
+
+        self.ensure_workspace_index(connection, token)?;
+
+        let locations = if let Some(Ok(exprs)) = self.parse_result(uri.as_str()) {
+            let sources = self.workspace_sources();
+            let locations = compute_references(
+                &uri,
+                exprs,
+                position,
+                &sources,
+                params.context.include_declaration,
+            );
+            self.encode_positions(uri.as_str(), locations)
+        } else {
+            None
+        };
+
+        let result = serde_json::to_value(locations).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_call_hierarchy_prepare(
+        &mut self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) =
+            req.extract::<CallHierarchyPrepareParams>(CallHierarchyPrepare::METHOD)?;
+
+        let position_params = params.text_document_position_params;
+        let uri = position_params.text_document.uri;
+        let position = self.decode_positions(uri.as_str(), position_params.position);
+
+        self.ensure_workspace_index(connection, params.work_done_progress_params.work_done_token)?;
+        let sources = self.workspace_sources();
+        let graph = CallGraph::new(&sources, &self.module_search_paths());
+
+        let items = sources
+            .iter()
+            .find(|(source_uri, _)| *source_uri == uri)
+            .and_then(|(_, exprs)| prepare_call_hierarchy(&graph, &uri, exprs, position));
+        let items = self.encode_positions(uri.as_str(), items);
+
+        let result = serde_json::to_value(items).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
+    }
+
+    pub fn handle_call_hierarchy_incoming_calls(
+        &mut self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) =
+            req.extract::<CallHierarchyIncomingCallsParams>(CallHierarchyIncomingCalls::METHOD)?;
+
+        self.ensure_workspace_index(connection, params.work_done_progress_params.work_done_token)?;
+        let sources = self.workspace_sources();
+        let graph = CallGraph::new(&sources, &self.module_search_paths());
+
+        let uri = params.item.uri.to_string();
+        let item = self.decode_positions(&uri, params.item);
+        let calls = self.encode_positions(&uri, incoming_calls(&graph, &item));
 
-use anyhow::anyhow;
-use lsp_server::{Connection, Message, Response};
-use lsp_types::{
-    notification::{DidChangeTextDocument, DidOpenTextDocument, Notification, PublishDiagnostics},
-    request::{DocumentSymbolRequest, Formatting, Request},
-    DidChangeTextDocumentParams, DidOpenTextDocumentParams, DocumentFormattingParams,
-    DocumentSymbolParams, DocumentSymbolResponse, Location, OneOf, Position,
-    PublishDiagnosticsParams, Range, ServerCapabilities, SymbolInformation, SymbolKind,
-    TextDocumentSyncKind, TextEdit, Uri,
-};
-use tan::{error::Error, expr::Expr};
-use tan_formatting::pretty::Formatter;
-use tan_lints::compute_diagnostics;
-use tracing::{info, trace};
+        let result = serde_json::to_value(Some(calls)).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
 
-use crate::util::{
-    dialect_from_document_uri, lsp_range_from_tan_range, lsp_range_top, make_analysis_context,
-    parse_module_file, parse_string_all, send_server_status_notification, VERSION,
-};
+        Ok(())
+    }
 
-// #insight
-// For debugging use trace! and similar functions, the traces are logged in the
-// `Tan Language` tab of the Output panel, in VS Code.
+    pub fn handle_call_hierarchy_outgoing_calls(
+        &mut self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) =
+            req.extract::<CallHierarchyOutgoingCallsParams>(CallHierarchyOutgoingCalls::METHOD)?;
 
-pub struct Server {
-    documents: HashMap<String, String>,
-    parsed_documents: HashMap<String, Result<Vec<Expr>, Vec<Error>>>,
-    // #todo also cache 'parsed/compiled' documents -> partial modules.
-}
+        self.ensure_workspace_index(connection, params.work_done_progress_params.work_done_token)?;
+        let sources = self.workspace_sources();
+        let graph = CallGraph::new(&sources, &self.module_search_paths());
 
-// #todo split further into methods.
+        // #insight the item ranges are compared with the definitions, decode first.
+        let uri = params.item.uri.to_string();
+        let item = self.decode_positions(&uri, params.item);
+        let calls = self.encode_positions(&uri, outgoing_calls(&graph, &item));
 
-impl Server {
-    pub fn new() -> Self {
-        Self {
-            documents: HashMap::default(),
-            parsed_documents: HashMap::default(),
-        }
+        let result = serde_json::to_value(Some(calls)).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
+
+        Ok(())
     }
 
-    pub fn run(&mut self) -> anyhow::Result<()> {
-        info!("Starting LSP server, v{}...", VERSION);
+    pub fn handle_code_lens(
+        &mut self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<CodeLensParams>(CodeLensRequest::METHOD)?;
 
-        let (connection, io_threads) = Connection::stdio();
+        let uri = params.text_document.uri;
 
-        let server_capabilities = serde_json::to_value(ServerCapabilities {
-            // definition_provider: Some(OneOf::Left(true)),
-            // references_provider: Some(OneOf::Left(true)),
-            // #insight Enables didOpen/didChange notifications.
-            document_symbol_provider: Some(OneOf::Left(true)),
-            text_document_sync: Some(lsp_types::TextDocumentSyncCapability::Kind(
-                TextDocumentSyncKind::FULL,
-            )),
-            rename_provider: Some(OneOf::Left(true)),
-            document_formatting_provider: Some(OneOf::Left(true)),
-            ..Default::default()
-        })
-        .unwrap();
+        self.ensure_workspace_index(connection, params.work_done_progress_params.work_done_token)?;
 
-        let initialization_params = connection.initialize(server_capabilities)?;
+        let lenses = if let Some(Ok(exprs)) = self.parse_result(uri.as_str()) {
+            let sources = self.workspace_sources();
+            let lenses = compute_code_lenses(&uri, exprs, &sources);
+            self.encode_positions(uri.as_str(), lenses)
+        } else {
+            Vec::new()
+        };
 
-        info!("Started.");
-        send_server_status_notification(&connection, "started")?;
+        let result = serde_json::to_value(Some(lenses)).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
 
-        // Run the server.
-        self.run_loop(connection, initialization_params)?;
+        Ok(())
+    }
 
-        // Wait for the two threads to end (typically by trigger LSP Exit event).
-        io_threads.join()?;
+    pub fn handle_completion(
+        &self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<CompletionParams>(Completion::METHOD)?;
+
+        let uri = params.text_document_position.text_document.uri;
+
+        // #insight the items have no ranges, no position conversion is needed.
+        let items = if let Some(Ok(exprs)) = self.parse_result(uri.as_str()) {
+            compute_completions(exprs, self.capabilities.snippets)
+        } else {
+            Vec::new()
+        };
 
-        info!("Shutting down server...");
+        let result = serde_json::to_value(Some(CompletionResponse::Array(items))).unwrap();
+        let resp = Response {
+            id,
+            result: Some(result),
+            error: None,
+        };
+        connection.sender.send(Message::Response(resp))?;
 
         Ok(())
     }
 
-    // #todo find a good name.
-    pub fn process_document(&mut self, uri: &Uri, text: &str) {
-        let input = text.to_string();
-        let uri = uri.to_string();
-        let exprs = parse_string_all(&input);
-        self.parsed_documents.insert(uri.clone(), exprs);
-        self.documents.insert(uri, input);
+    /// Returns the source of a document, open documents take precedence over
+    /// the files on disk.
+    pub fn document_source(&self, uri: &str) -> Option<String> {
+        if let Some(input) = self.documents.get(uri) {
+            return Some(input.clone());
+        }
+
+        std::fs::read_to_string(path_from_uri(uri)?).ok()
     }
 
-    // #todo return a more precise result.
-    pub fn send_diagnostics(&self, connection: &Connection, uri: Uri) -> anyhow::Result<()> {
-        let Some(parse_result) = self.parsed_documents.get(uri.as_str()) else {
-            return Err(anyhow!("invalid document").context("in send_diagnostics"));
+    pub fn handle_execute_command(
+        &self,
+        connection: &Connection,
+        req: lsp_server::Request,
+    ) -> anyhow::Result<()> {
+        let (id, params) = req.extract::<ExecuteCommandParams>(ExecuteCommand::METHOD)?;
+
+        let result = match params.command.as_str() {
+            RUN_COMMAND | TEST_COMMAND => self.run_module(connection, &params),
+            EVALUATE_SELECTION_COMMAND => self.evaluate_selection(connection, &params),
+            command => Err(anyhow!("unknown command `{command}`")),
         };
 
-        let diagnostics = compute_diagnostics(parse_result);
+        let resp = match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(error) => Response::new_err(
+                id,
+                lsp_server::ErrorCode::InvalidParams as i32,
+                error.to_string(),
+            ),
+        };
+        connection.sender.send(Message::Response(resp))?;
 
-        let pdm = PublishDiagnosticsParams {
-            uri: uri.clone(),
-            diagnostics,
-            version: None,
+        Ok(())
+    }
+
+    /// Evaluates a module and calls its `main` or test function, the output is
+    /// streamed to the client via `window/logMessage`.
+    pub fn run_module(
+        &self,
+        connection: &Connection,
+        params: &ExecuteCommandParams,
+    ) -> anyhow::Result<serde_json::Value> {
+        let args = &params.arguments;
+        let uri = args
+            .first()
+            .and_then(|arg| arg.as_str())
+            .unwrap_or_default();
+
+        let Some(mut input) = self.document_source(uri) else {
+            return Err(anyhow!("unknown document `{uri}`"));
         };
 
-        let notification = lsp_server::Notification {
-            method: PublishDiagnostics::METHOD.to_owned(),
-            params: serde_json::to_value(pdm).unwrap(),
+        // #insight the module is evaluated first, then the entry function is called.
+        if params.command == TEST_COMMAND {
+            let name = args.get(1).and_then(|arg| arg.as_str()).unwrap_or_default();
+
+            // #insight the name is spliced into the evaluated input, accept only the test functions of the document.
+            let is_defined =
+                parse_string_all(&input).is_ok_and(|exprs| is_test_func_defined(&exprs, name));
+            if !is_defined {
+                return Err(anyhow!("unknown test function `{name}`"));
+            }
+
+            input.push_str(&format!("\n({name})\n"));
+        } else {
+            input.push_str("\n(main)\n");
+        }
+
+        self.publish_status(connection, "running")?;
+
+        let result = eval_sandboxed(input, uri.to_string(), &self.config.eval, |line| {
+            let _ = send_log_message_notification(connection, MessageType::LOG, line);
+        });
+
+        let result = match result {
+            Ok(Ok(value)) => value,
+            Ok(Err(errors)) => {
+                for error in &errors {
+                    send_log_message_notification(connection, MessageType::ERROR, error.clone())?;
+                }
+                errors.join("\n")
+            }
+            Err(_) => {
+                let message = format!(
+                    "evaluation timed out after {:?}",
+                    self.config.eval.timeout()
+                );
+                send_log_message_notification(connection, MessageType::ERROR, message.clone())?;
+                message
+            }
         };
 
-        connection
-            .sender
-            .send(Message::Notification(notification))?;
+        self.publish_status(connection, "ran")?;
+
+        Ok(serde_json::to_value(Some(result)).unwrap())
+    }
+
+    /// Evaluates the top-level forms covered by the selection, seeded with the
+    /// preceding `let` definitions of the document.
+    pub fn evaluate_selection(
+        &self,
+        connection: &Connection,
+        params: &ExecuteCommandParams,
+    ) -> anyhow::Result<serde_json::Value> {
+        let args = &params.arguments;
+        let uri = args
+            .first()
+            .and_then(|arg| arg.as_str())
+            .unwrap_or_default();
+
+        let Some(range) = args
+            .get(1)
+            .and_then(|arg| serde_json::from_value::<Range>(arg.clone()).ok())
+        else {
+            return Err(anyhow!("missing selection range"));
+        };
+
+        let (Some(Ok(exprs)), Some(input)) = (self.parse_result(uri), self.documents.get(uri))
+        else {
+            return Err(anyhow!("unknown or invalid document `{uri}`"));
+        };
+
+        let selection = self.decode_positions(uri, range);
+
+        let Some((_, _, range)) = select_forms(exprs, selection) else {
+            return Err(anyhow!("no expression at the selection"));
+        };
+
+        self.publish_status(connection, "evaluating")?;
+
+        let mut output = Vec::new();
+
+        let result = eval_selection_sandboxed(
+            input.clone(),
+            uri.to_string(),
+            selection,
+            &self.config.eval,
+            |line| output.push(line),
+        );
+
+        let (value, errors) = match result {
+            Ok(Ok(value)) => (Some(value), Vec::new()),
+            Ok(Err(errors)) => (None, errors),
+            Err(_) => (
+                None,
+                vec![format!(
+                    "evaluation timed out after {:?}",
+                    self.config.eval.timeout()
+                )],
+            ),
+        };
+
+        self.publish_status(connection, "evaluated")?;
+
+        let result = self.encode_positions(
+            uri,
+            EvaluationResult {
+                range,
+                value,
+                errors,
+                output,
+            },
+        );
+
+        Ok(serde_json::to_value(result).unwrap())
+    }
+
+    /// Pulls the configuration with a `workspace/configuration` request, the
+    /// response is handled in `run_loop`.
+    pub fn request_configuration(&mut self, connection: &Connection) -> anyhow::Result<()> {
+        self.next_request_id += 1;
+        let id = lsp_server::RequestId::from(self.next_request_id);
+
+        let params = ConfigurationParams {
+            items: vec![ConfigurationItem {
+                scope_uri: None,
+                section: Some(CONFIGURATION_SECTION.to_string()),
+            }],
+        };
+
+        let req = lsp_server::Request::new(
+            id.clone(),
+            WorkspaceConfiguration::METHOD.to_owned(),
+            params,
+        );
+        connection.sender.send(Message::Request(req))?;
+
+        self.configuration_request_id = Some(id);
+
+        Ok(())
+    }
+
+    /// Watches the project configuration file and the Tan files of the
+    /// workspace, if the client supports dynamic registration.
+    pub fn register_file_watchers(&mut self, connection: &Connection) -> anyhow::Result<()> {
+        // #insight only the configuration file at the workspace root is watched.
+        let Some(root) = &self.workspace_root else {
+            return Ok(());
+        };
+
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(
+                        root.join(PROJECT_CONFIG_FILE_NAME).display().to_string(),
+                    ),
+                    kind: None,
+                },
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(String::from("**/*.tan")),
+                    kind: None,
+                },
+            ],
+        };
+
+        self.next_request_id += 1;
+        let id = lsp_server::RequestId::from(self.next_request_id);
+
+        let params = RegistrationParams {
+            registrations: vec![Registration {
+                id: String::from("tan-file-watchers"),
+                method: DidChangeWatchedFiles::METHOD.to_owned(),
+                register_options: Some(serde_json::to_value(options).unwrap()),
+            }],
+        };
+
+        let req = lsp_server::Request::new(id, RegisterCapability::METHOD.to_owned(), params);
+        connection.sender.send(Message::Request(req))?;
+
+        Ok(())
+    }
+
+    /// Loads the project configuration file from the workspace root, or from
+    /// the given (unsaved) input.
+    pub fn load_project_config(
+        &mut self,
+        connection: &Connection,
+        input: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let result = match (input, &self.workspace_root) {
+            (Some(input), _) => Some(parse_project_config(input)),
+            (None, Some(root)) => read_project_config(root),
+            (None, None) => None,
+        };
+
+        // #insight a valid configuration clears the previous error, unless
+        // the last error has another source.
+        let previous_error = self.project_config_error.take();
+        self.clear_last_error(previous_error);
+
+        self.project_settings = match result {
+            Some(Ok(settings)) => settings,
+            Some(Err(error)) => {
+                let message = format!("Invalid {PROJECT_CONFIG_FILE_NAME}: {error}");
+                send_log_message_notification(connection, MessageType::WARNING, message.clone())?;
+                self.status.last_error = Some(message.clone());
+                self.project_config_error = Some(message);
+                serde_json::Value::Null
+            }
+            None => serde_json::Value::Null,
+        };
+
+        Ok(())
+    }
+
+    /// Computes the configuration from the editor and the project settings,
+    /// the project settings take precedence. Invalid fields fall back to their
+    /// defaults, and are reported to the user.
+    pub fn merge_config(&mut self, connection: &Connection) -> anyhow::Result<()> {
+        let mut settings = settings_section(&self.editor_settings).clone();
+        if !settings.is_object() {
+            settings = serde_json::Value::Object(Default::default());
+        }
+        merge_json(&mut settings, &self.project_settings);
+
+        let (config, errors) = ServerConfig::from_value(&settings);
+        self.config = config;
+
+        let previous_error = self.settings_error.take();
+        self.clear_last_error(previous_error);
+
+        if !errors.is_empty() {
+            let message = format!("Invalid configuration: {}", errors.join(", "));
+            send_log_message_notification(connection, MessageType::WARNING, message.clone())?;
+            self.status.last_error = Some(message.clone());
+            self.settings_error = Some(message);
+        }
 
         Ok(())
     }
 
+    /// Clears the last error, if it is the given (resolved) error.
+    fn clear_last_error(&mut self, error: Option<String>) {
+        if error.is_some() && self.status.last_error == error {
+            self.status.last_error = None;
+        }
+    }
+
+    pub fn module_search_paths(&self) -> Vec<PathBuf> {
+        self.config
+            .module_search_paths(self.workspace_root.as_deref())
+    }
+
+    /// Recomputes the configuration, and re-publishes the diagnostics of the
+    /// open documents.
+    pub fn update_config(&mut self, connection: &Connection) -> anyhow::Result<()> {
+        self.merge_config(connection)?;
+
+        trace!("Updated configuration: {:?}.", self.config);
+
+        // #insight the configuration affects the indexed files, e.g.
+        // `workspace.exclude`.
+        self.workspace_index_stale = true;
+
+        // #insight the configuration affects the analysis, e.g. `eval.sandbox`.
+        let uris: Vec<String> = self.documents.keys().cloned().collect();
+        for uri in &uris {
+            self.analysis_diagnostics.remove(uri);
+            self.analysis_bindings.remove(uri);
+            self.schedule_analysis(uri);
+        }
+
+        if self.capabilities.pull_diagnostics {
+            self.request_diagnostic_refresh(connection)?;
+            return self.set_state(connection, self.idle_state(), None);
+        }
+
+        if uris.is_empty() {
+            return self.set_state(connection, self.idle_state(), None);
+        }
+
+        let progress = self.start_progress(connection, None, "Checking documents", true)?;
+
+        for (i, uri) in uris.iter().enumerate() {
+            if let Some(progress) = &progress {
+                // #insight the remaining documents are re-checked on change.
+                if self.is_progress_cancelled(connection, progress) {
+                    break;
+                }
+                progress.report(uri, percentage(i, uris.len()))?;
+            }
+
+            if let Ok(uri) = Uri::from_str(uri) {
+                self.send_diagnostics(connection, uri)?;
+            }
+        }
+
+        if let Some(progress) = progress {
+            self.end_progress(progress, "checked")?;
+        }
+
+        self.set_state(connection, self.idle_state(), None)
+    }
+
+    /// Handles `shutdown`, stops accepting requests, cancels the background
+    /// analyses and drops the documents.
+    pub fn shutdown(&mut self) {
+        info!("Shutdown requested.");
+
+        self.shutdown_requested = true;
+
+        self.analysis.cancel_all();
+        self.analysis_diagnostics.clear();
+        self.analysis_bindings.clear();
+
+        // #todo persist the index cache here, once it is stored on disk.
+        self.documents.clear();
+        self.parsed_documents.clear();
+        self.line_indexes.clear();
+        self.diagnostic_counts.clear();
+    }
+
     pub fn run_loop(
         &mut self,
         connection: Connection,
-        _params: serde_json::Value,
-    ) -> anyhow::Result<()> {
-        // #todo use params to get root_uri and perform initial diagnostics for all files.
-        // let params: InitializeParams = serde_json::from_value(params).unwrap();
-        // eprintln!("{params:#?}");
+        params: serde_json::Value,
+    ) -> anyhow::Result<ShutdownStatus> {
+        // #insight the client capabilities are negotiated in `serve`.
+        // #todo perform initial diagnostics for all files.
+
+        // #todo also consider the workspace folders.
+        self.workspace_root = params
+            .get("rootUri")
+            .and_then(|root_uri| root_uri.as_str())
+            .and_then(path_from_uri);
+
+        if let Some(options) = params.get("initializationOptions") {
+            self.editor_settings = options.clone();
+        }
+
+        if self.watch_parent_process {
+            if let Some(pid) = params.get("processId").and_then(|pid| pid.as_u64()) {
+                spawn_parent_process_watchdog(pid as u32);
+            }
+        }
+
+        self.load_project_config(&connection, None)?;
+        self.merge_config(&connection)?;
+        self.set_state(&connection, self.idle_state(), None)?;
 
-        // #insight we cache the analysis context out of the loop.
-        let mut analysis_context = make_analysis_context().unwrap();
+        if self.capabilities.watched_files_registration {
+            self.register_file_watchers(&connection)?;
+        }
+
+        if self.capabilities.configuration_pull {
+            self.request_configuration(&connection)?;
+        }
 
-        for msg in &connection.receiver {
+        while let Some(msg) = self.next_message(&connection)? {
             trace!("Got msg: {:?}.", msg);
             match msg {
                 Message::Request(req) => {
-                    if connection.handle_shutdown(&req)? {
-                        return Ok(());
-                    }
                     trace!("got request: {:?}", req);
-                    // match cast::<GotoDefinition>(req.clone()) {
-                    //     Ok((id, params)) => {
-                    //         eprintln!("got gotoDefinition request #{id}: {params:?}");
-                    //         let result = Some(GotoDefinitionResponse::Array(Vec::new()));
-                    //         let result = serde_json::to_value(&result).unwrap();
-                    //         let resp = Response {
-                    //             id,
-                    //             result: Some(result),
-                    //             error: None,
-                    //         };
-                    //         connection.sender.send(Message::Response(resp))?;
-                    //         continue;
-                    //     }
-                    //     Err(err @ ExtractError::JsonError { .. }) => panic!("{err:?}"),
-                    //     Err(ExtractError::MethodMismatch(req)) => req,
-                    // };
-                    // match cast::<References>(req.clone()) {
-                    //     Ok((id, params)) => {
-                    //         eprintln!("got references request #{id}: {params:?}");
-                    //         let result = Some(Vec::<String>::new());
-                    //         let result = serde_json::to_value(&result).unwrap();
-                    //         let resp = Response {
-                    //             id,
-                    //             result: Some(result),
-                    //             error: None,
-                    //         };
-                    //         connection.sender.send(Message::Response(resp))?;
-                    //         continue;
-                    //     }
-                    //     Err(err @ ExtractError::JsonError { .. }) => panic!("{err:?}"),
-                    //     Err(ExtractError::MethodMismatch(req)) => req,
-                    // };
+
+                    // #insight after `shutdown`, requests are rejected.
+                    if self.shutdown_requested {
+                        let resp = Response::new_err(
+                            req.id,
+                            lsp_server::ErrorCode::InvalidRequest as i32,
+                            String::from("the server is shutting down"),
+                        );
+                        connection.sender.send(Message::Response(resp))?;
+                        continue;
+                    }
+
+                    if req.method == Shutdown::METHOD {
+                        self.shutdown();
+                        connection
+                            .sender
+                            .send(Message::Response(Response::new_ok(req.id, ())))?;
+                        continue;
+                    }
 
                     // #todo also handle "textDocument/hover".
 
@@ -174,87 +1677,34 @@ impl Server {
 
                             let (id, params) =
                                 req.extract::<DocumentSymbolParams>(DocumentSymbolRequest::METHOD)?;
-                            // #todo Flat (SymbolInformation) vs Nested (DocumentSymbol)
-
-                            // #insight, actually Flat works just fine, Nested is too noisy.
-
-                            // #todo for some reason, the Nested form was not working! investigate.
-                            // #todo maybe we need to populate `children`?
-                            // #[allow(deprecated)]
-                            // let _ds = DocumentSymbol {
-                            //     name: String::from("dummy"),
-                            //     detail: None,
-                            //     kind: SymbolKind::FUNCTION,
-                            //     tags: None,
-                            //     deprecated: None,
-                            //     range,
-                            //     selection_range: range,
-                            //     children: None,
-                            // };
-
-                            // #todo cache the parsing between documentSymbol, formatting, linting etc!
-
-                            let Some(Ok(exprs)) =
-                                self.parsed_documents.get(params.text_document.uri.as_str())
-                            else {
-                                // #todo what should be done here?
-                                trace!("!!!!! should NOT happen?");
-                                continue;
-                            };
+                            // #insight Flat (SymbolInformation) or Nested (DocumentSymbol), depending on the client.
 
-                            let Ok(scope) = parse_module_file(exprs, &mut analysis_context) else {
-                                // #todo what to do here?
+                            let uri = params.text_document.uri.as_str();
+
+                            // #insight unknown documents have no symbols.
+                            if !self.documents.contains_key(uri) {
+                                connection.sender.send(Message::Response(Response::new_ok(
+                                    id,
+                                    serde_json::Value::Null,
+                                )))?;
                                 continue;
-                            };
-                            let bindings = scope.bindings.read().expect("not poisoned");
-
-                            let mut infos: Vec<SymbolInformation> = Vec::new();
-
-                            // #insight VS Code automatically sorts the documentSymbols in source/range order.
-
-                            for (name, expr) in bindings.iter() {
-                                let range = if let Some(tan_range) = expr.range() {
-                                    lsp_range_from_tan_range(tan_range)
-                                } else {
-                                    // #todo is this clause really needed?
-                                    lsp_range_top()
-                                };
-
-                                let location = Location {
-                                    uri: params.text_document.uri.clone(),
-                                    range,
-                                };
-
-                                let Expr::Type(typ) = expr.dyn_type(&analysis_context) else {
-                                    // #todo should never happen.
-                                    // #todo dyn_type has not a convenient interface here.
-                                    panic!("cannot infere dynamic type");
-                                };
-
-                                let kind = match typ.as_str() {
-                                    // #todo add more variants here!
-                                    "Func" => SymbolKind::FUNCTION,
-                                    _ => SymbolKind::VARIABLE,
-                                };
-
-                                // #insight VS Code Outline automatically sorts by range.
-                                // #insight if a symbol range includes other symbol ranges, VS Code automatically nests.
-
-                                #[allow(deprecated)]
-                                infos.push(SymbolInformation {
-                                    name: name.clone(),
-                                    kind,
-                                    tags: None,
-                                    deprecated: None,
-                                    location: location.clone(),
-                                    container_name: None,
-                                });
                             }
 
-                            // #todo maybe it needs children array populated?
-                            // let result = DocumentSymbolResponse::Nested(vec![ds]);
+                            let exprs = match self.parse_result(uri) {
+                                Some(Ok(exprs)) => exprs.as_slice(),
+                                _ => &[],
+                            };
+
+                            // #insight the symbols of the latest background
+                            // analysis, the main loop never evaluates.
+                            let result = compute_document_symbol_response(
+                                &params.text_document.uri,
+                                exprs,
+                                self.analysis_bindings.get(uri).cloned(),
+                                self.capabilities.hierarchical_document_symbols,
+                            );
 
-                            let result = DocumentSymbolResponse::Flat(infos);
+                            let result = self.encode_positions(uri, result);
                             let result =
                                 serde_json::to_value::<DocumentSymbolResponse>(result).unwrap();
                             let resp = Response {
@@ -265,61 +1715,84 @@ impl Server {
                             connection.sender.send(Message::Response(resp))?;
                         }
                         Formatting::METHOD => {
-                            send_server_status_notification(&connection, "formatting")?;
-
-                            let (id, params) =
-                                req.extract::<DocumentFormattingParams>(Formatting::METHOD)?;
-
-                            let document = params.text_document;
-
-                            let Some(input) = self.documents.get(document.uri.as_str()) else {
-                                return Err(
-                                    anyhow!("Unknown document").context("in Formatting::METHOD")
-                                );
-                            };
-
-                            // #todo don't parse all the time? is this even possible, probably not the input changed here.
-
-                            let Ok(exprs) = parse_string_all(input) else {
-                                return Err(anyhow::anyhow!("Error"));
-                            };
-
-                            let dialect = dialect_from_document_uri(document.uri.as_str());
-
-                            let formatter = Formatter::for_dialect(&exprs, dialect);
-                            let formatted = formatter.format();
-
-                            // #todo does it make sense to compute diffs?
-
-                            // #todo extract as helper!
-                            // Select the whole document for replacement
-                            let start = Position::new(0, 0);
-                            let end = Position::new(u32::MAX, u32::MAX);
-                            let document_range = Range::new(start, end);
-
-                            let result = Some(vec![TextEdit::new(document_range, formatted)]);
-                            let result = serde_json::to_value(&result).unwrap();
-                            let resp = Response {
-                                id,
-                                result: Some(result),
-                                error: None,
-                            };
-
-                            send_server_status_notification(&connection, "formatted")?;
-
-                            connection.sender.send(Message::Response(resp))?;
-
-                            continue;
+                            self.handle_formatting(&connection, req)?;
+                        }
+                        GotoDefinition::METHOD => {
+                            self.handle_goto_definition(&connection, req)?;
+                        }
+                        References::METHOD => {
+                            self.handle_references(&connection, req)?;
+                        }
+                        DocumentDiagnosticRequest::METHOD => {
+                            self.handle_document_diagnostic(&connection, req)?;
+                        }
+                        ExpandMacro::METHOD => {
+                            self.handle_expand_macro(&connection, req)?;
+                        }
+                        SyntaxTree::METHOD => {
+                            self.handle_syntax_tree(&connection, req)?;
+                        }
+                        DocumentLinkRequest::METHOD => {
+                            self.handle_document_link(&connection, req)?;
+                        }
+                        InlayHintRequest::METHOD => {
+                            self.handle_inlay_hint(&connection, req)?;
+                        }
+                        InlayHintResolveRequest::METHOD => {
+                            self.handle_inlay_hint_resolve(&connection, req)?;
+                        }
+                        CallHierarchyPrepare::METHOD => {
+                            self.handle_call_hierarchy_prepare(&connection, req)?;
+                        }
+                        CallHierarchyIncomingCalls::METHOD => {
+                            self.handle_call_hierarchy_incoming_calls(&connection, req)?;
+                        }
+                        CallHierarchyOutgoingCalls::METHOD => {
+                            self.handle_call_hierarchy_outgoing_calls(&connection, req)?;
+                        }
+                        CodeLensRequest::METHOD => {
+                            self.handle_code_lens(&connection, req)?;
+                        }
+                        Completion::METHOD => {
+                            self.handle_completion(&connection, req)?;
+                        }
+                        ExecuteCommand::METHOD => {
+                            self.handle_execute_command(&connection, req)?;
                         }
                         _ => continue,
                     }
                 }
                 Message::Response(resp) => {
                     trace!("Got response: {:?}.", resp);
+
+                    if self.configuration_request_id.as_ref() == Some(&resp.id) {
+                        self.configuration_request_id = None;
+
+                        // #insight the result is an array, one item per requested section.
+                        if let Some(settings) =
+                            resp.result.as_ref().and_then(|result| result.get(0))
+                        {
+                            self.editor_settings = settings.clone();
+                            self.update_config(&connection)?;
+                        }
+                    }
                 }
                 Message::Notification(notification) => {
                     info!("got notification: {:?}.", notification);
 
+                    if notification.method == Exit::METHOD {
+                        return Ok(if self.shutdown_requested {
+                            ShutdownStatus::Clean
+                        } else {
+                            ShutdownStatus::Unexpected
+                        });
+                    }
+
+                    // #insight after `shutdown`, notifications are dropped.
+                    if self.shutdown_requested {
+                        continue;
+                    }
+
                     match notification.method.as_ref() {
                         "textDocument/didOpen" => {
                             if let Ok(params) = notification
@@ -330,6 +1803,13 @@ impl Server {
                                 self.send_diagnostics(&connection, document.uri)?;
                             }
                         }
+                        "textDocument/didClose" => {
+                            if let Ok(params) = notification
+                                .extract::<DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD)
+                            {
+                                self.close_document(&connection, params.text_document.uri)?;
+                            }
+                        }
                         "textDocument/didChange" => {
                             // #todo #perf support incremental updates for formatting, documentSymbols, etc...
                             if let Ok(params) = notification.extract::<DidChangeTextDocumentParams>(
@@ -339,10 +1819,62 @@ impl Server {
                                 let changes = params.content_changes;
                                 if let Some(change) = changes.first() {
                                     self.process_document(&document.uri, &change.text);
-                                    self.send_diagnostics(&connection, document.uri)?;
+                                    self.send_diagnostics(&connection, document.uri.clone())?;
+
+                                    // #insight apply unsaved edits of the project configuration.
+                                    if is_project_config_uri(
+                                        document.uri.as_str(),
+                                        self.workspace_root.as_deref(),
+                                    ) {
+                                        self.load_project_config(&connection, Some(&change.text))?;
+                                        self.update_config(&connection)?;
+                                    }
+                                }
+                            }
+                        }
+                        "workspace/didChangeConfiguration" => {
+                            if let Ok(params) = notification
+                                .extract::<DidChangeConfigurationParams>(
+                                    DidChangeConfiguration::METHOD,
+                                )
+                            {
+                                // #insight clients using the pull model send empty settings.
+                                if !settings_section(&params.settings).is_null() {
+                                    self.editor_settings = params.settings;
+                                    self.update_config(&connection)?;
+                                } else if self.capabilities.configuration_pull {
+                                    self.request_configuration(&connection)?;
+                                }
+                            }
+                        }
+                        "workspace/didChangeWatchedFiles" => {
+                            if let Ok(params) = notification.extract::<DidChangeWatchedFilesParams>(
+                                DidChangeWatchedFiles::METHOD,
+                            ) {
+                                let root = self.workspace_root.as_deref();
+                                let (config_changes, file_changes): (Vec<_>, Vec<_>) =
+                                    params.changes.iter().partition(|change| {
+                                        is_project_config_uri(change.uri.as_str(), root)
+                                    });
+
+                                if !config_changes.is_empty() {
+                                    self.load_project_config(&connection, None)?;
+                                    self.update_config(&connection)?;
+                                }
+
+                                // #insight only new and modified files are parsed.
+                                if file_changes
+                                    .iter()
+                                    .any(|change| change.uri.as_str().ends_with(".tan"))
+                                {
+                                    self.update_workspace_index(&connection, None)?;
                                 }
                             }
                         }
+                        WorkDoneProgressCancel::METHOD => {
+                            // #insight the progress ended before the cancellation arrived.
+                            trace!("Ignored cancellation: {:?}.", notification.params);
+                        }
                         _ => {
                             eprintln!("Unhandled: {}", notification.method);
                         }
@@ -360,6 +1892,8 @@ impl Server {
                 }
             }
         }
-        Ok(())
+
+        // #insight the client disconnected without `exit`.
+        Ok(ShutdownStatus::Unexpected)
     }
 }
@@ -0,0 +1,125 @@
+use lsp_server::{Connection, Message};
+use lsp_types::notification::Notification;
+use serde::{Deserialize, Serialize};
+
+use crossbeam::channel::SendError;
+
+// #insight
+// The status is rendered by the editor extension, e.g. as a status bar item.
+// The free-form `text` predates the structured fields, it is kept for older
+// extensions.
+
+#[derive(Debug)]
+pub enum PublishServerStatus {}
+
+impl Notification for PublishServerStatus {
+    type Params = PublishServerStatusParams;
+    const METHOD: &'static str = "tan/publishServerStatus";
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ServerState {
+    #[default]
+    Starting,
+    /// The documents are (re-)analyzed, e.g. after a configuration change.
+    Indexing,
+    Ready,
+    /// The server is degraded, e.g. the project configuration is invalid.
+    Error,
+}
+
+impl ServerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerState::Starting => "starting",
+            ServerState::Indexing => "indexing",
+            ServerState::Ready => "ready",
+            ServerState::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    #[serde(default)]
+    pub state: ServerState,
+    /// The progress of the current operation, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<u32>,
+    #[serde(default)]
+    pub indexed_files: usize,
+    #[serde(default)]
+    pub diagnostics: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublishServerStatusParams {
+    pub text: String,
+    #[serde(flatten)]
+    pub status: ServerStatus,
+}
+
+pub fn send_server_status_notification(
+    connection: &Connection,
+    text: &str,
+    status: &ServerStatus,
+) -> Result<(), SendError<Message>> {
+    let text = format!("👅 {text}");
+
+    let pss = PublishServerStatusParams {
+        text,
+        status: status.clone(),
+    };
+
+    let notification = lsp_server::Notification {
+        method: PublishServerStatus::METHOD.to_owned(),
+        params: serde_json::to_value(pss).unwrap(),
+    };
+
+    connection
+        .sender
+        .send(Message::Notification(notification))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::status::{PublishServerStatusParams, ServerState, ServerStatus};
+
+    #[test]
+    fn publish_server_status_params_usage() {
+        let params = PublishServerStatusParams {
+            text: String::from("👅 indexing"),
+            status: ServerStatus {
+                state: ServerState::Indexing,
+                progress: Some(50),
+                indexed_files: 2,
+                diagnostics: 3,
+                last_error: None,
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&params).unwrap(),
+            json!({
+                "text": "👅 indexing",
+                "state": "indexing",
+                "progress": 50,
+                "indexedFiles": 2,
+                "diagnostics": 3,
+            })
+        );
+
+        // #insight the text-only params of older servers are still valid.
+        let params: PublishServerStatusParams =
+            serde_json::from_value(json!({ "text": "👅 started" })).unwrap();
+        assert_eq!(params.status.state, ServerState::Starting);
+    }
+}
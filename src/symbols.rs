@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use lsp_types::{
+    DocumentSymbol, DocumentSymbolResponse, Location, SymbolInformation, SymbolKind, Uri,
+};
+use tan::expr::Expr;
+use tracing::trace;
+
+use crate::{
+    analysis::AnalyzedBinding,
+    util::{let_bindings, lsp_range_from_tan_range, lsp_range_top},
+};
+
+/// Classifies a symbol by the dynamic type of its value.
+pub fn symbol_kind_from_type(typ: &str) -> SymbolKind {
+    match typ {
+        "Func" | "ForeignFunc" | "Macro" => SymbolKind::FUNCTION,
+        "Array" => SymbolKind::ARRAY,
+        "Map" => SymbolKind::OBJECT,
+        "String" => SymbolKind::STRING,
+        "Int" | "Float" | "Dec" | "U8" => SymbolKind::NUMBER,
+        "Bool" => SymbolKind::BOOLEAN,
+        // #todo add more variants here!
+        _ => SymbolKind::VARIABLE,
+    }
+}
+
+/// Classifies a symbol by the syntax of its (unevaluated) value, used when the
+/// dynamic type cannot be inferred.
+pub fn symbol_kind_from_syntax(value: &Expr) -> SymbolKind {
+    if let Some(op) = value
+        .as_list()
+        .and_then(|terms| terms.first())
+        .and_then(|op| op.as_symbol())
+    {
+        return symbol_kind_from_type(op);
+    }
+
+    if value.as_string().is_some() {
+        return SymbolKind::STRING;
+    }
+
+    SymbolKind::VARIABLE
+}
+
+pub fn compute_document_symbols(
+    uri: &Uri,
+    bindings: Vec<AnalyzedBinding>,
+    exprs: &[Expr],
+) -> Vec<SymbolInformation> {
+    // #insight the unevaluated values, for syntax-based classification.
+    let values: HashMap<&str, &Expr> = exprs
+        .iter()
+        .flat_map(let_bindings)
+        .filter_map(|(name, value)| Some((name.as_symbol()?, value)))
+        .collect();
+
+    let mut infos: Vec<SymbolInformation> = Vec::new();
+
+    // #insight VS Code automatically sorts the documentSymbols in source/range order.
+
+    for binding in bindings {
+        // #todo is the fallback really needed?
+        let range = binding.range.unwrap_or_else(lsp_range_top);
+
+        let location = Location {
+            uri: uri.clone(),
+            range,
+        };
+
+        let kind = if let Some(typ) = &binding.typ {
+            symbol_kind_from_type(typ)
+        } else if let Some(value) = values.get(binding.name.as_str()) {
+            trace!(
+                "cannot infer dynamic type of `{}`, classify by syntax",
+                binding.name
+            );
+            symbol_kind_from_syntax(value)
+        } else {
+            trace!("cannot infer dynamic type of `{}`", binding.name);
+            SymbolKind::VARIABLE
+        };
+
+        // #insight VS Code Outline automatically sorts by range.
+        // #insight if a symbol range includes other symbol ranges, VS Code automatically nests.
+
+        #[allow(deprecated)]
+        infos.push(SymbolInformation {
+            name: binding.name,
+            kind,
+            tags: None,
+            deprecated: None,
+            location,
+            container_name: None,
+        });
+    }
+
+    infos
+}
+
+/// Converts flat symbols to (top-level) nested symbols.
+pub fn nest_document_symbols(infos: Vec<SymbolInformation>) -> Vec<DocumentSymbol> {
+    infos
+        .into_iter()
+        .map(|info| {
+            // #insight `children` is optional, the top-level bindings have no children.
+            #[allow(deprecated)]
+            DocumentSymbol {
+                name: info.name,
+                detail: None,
+                kind: info.kind,
+                tags: None,
+                deprecated: None,
+                range: info.location.range,
+                selection_range: info.location.range,
+                children: None,
+            }
+        })
+        .collect()
+}
+
+/// The (untyped) top-level `let` bindings of the parsed expressions, used
+/// until the analysis of the document completes.
+pub fn syntactic_bindings(exprs: &[Expr]) -> Vec<AnalyzedBinding> {
+    exprs
+        .iter()
+        .flat_map(let_bindings)
+        .filter_map(|(name, value)| {
+            Some(AnalyzedBinding {
+                name: name.as_symbol()?.to_string(),
+                range: value.range().map(lsp_range_from_tan_range),
+                typ: None,
+            })
+        })
+        .collect()
+}
+
+/// Computes the document outline, as returned by `textDocument/documentSymbol`,
+/// from the bindings of the analysis, or the syntactic bindings if the
+/// document is not analyzed (yet). Returns nested symbols if `hierarchical`,
+/// i.e. if the client supports them.
+pub fn compute_document_symbol_response(
+    uri: &Uri,
+    exprs: &[Expr],
+    bindings: Option<Vec<AnalyzedBinding>>,
+    hierarchical: bool,
+) -> DocumentSymbolResponse {
+    let bindings = bindings.unwrap_or_else(|| syntactic_bindings(exprs));
+
+    let infos = compute_document_symbols(uri, bindings, exprs);
+
+    if hierarchical {
+        DocumentSymbolResponse::Nested(nest_document_symbols(infos))
+    } else {
+        DocumentSymbolResponse::Flat(infos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use lsp_types::{DocumentSymbolResponse, SymbolKind, Uri};
+
+    use crate::{
+        analysis::{analyze_module, AnalyzedBinding},
+        config::EvalConfig,
+        eval::Cancellation,
+        symbols::{compute_document_symbol_response, compute_document_symbols, syntactic_bindings},
+        util::parse_string_all,
+    };
+
+    fn symbol_kind(symbols: &[lsp_types::SymbolInformation], name: &str) -> SymbolKind {
+        symbols.iter().find(|s| s.name == name).unwrap().kind
+    }
+
+    #[test]
+    fn compute_document_symbols_classifies_unusual_bindings() {
+        let input = r#"
+        (let zonk (Func [x y] (+ x y)))
+        (let my-macro (Macro [x] x))
+        (let name #String "tan")
+        (let items [1 2 3])
+        (let plus +)
+        "#;
+
+        let uri = Uri::from_str("file:///project/main.tan").unwrap();
+        let exprs = parse_string_all(input).unwrap();
+        let analysis = analyze_module(
+            input.to_string(),
+            uri.to_string(),
+            Vec::new(),
+            &EvalConfig::default(),
+            &Cancellation::default(),
+        )
+        .unwrap();
+        let symbols = compute_document_symbols(&uri, analysis.bindings, &exprs);
+
+        assert_eq!(symbol_kind(&symbols, "zonk"), SymbolKind::FUNCTION);
+        assert_eq!(symbol_kind(&symbols, "my-macro"), SymbolKind::FUNCTION);
+        assert_eq!(symbol_kind(&symbols, "name"), SymbolKind::STRING);
+        assert_eq!(symbol_kind(&symbols, "items"), SymbolKind::ARRAY);
+        // #insight a foreign function value.
+        assert_eq!(symbol_kind(&symbols, "plus"), SymbolKind::FUNCTION);
+    }
+
+    #[test]
+    fn compute_document_symbols_falls_back_to_syntax() {
+        let input = r#"
+        (let zonk (Func [x y] (+ x y)))
+        (let greeting "hello")
+        "#;
+
+        let uri = Uri::from_str("file:///project/main.tan").unwrap();
+        let exprs = parse_string_all(input).unwrap();
+
+        // #insight simulate bindings with un-inferable dynamic types.
+        let bindings = ["zonk", "greeting", "unknown"]
+            .into_iter()
+            .map(|name| AnalyzedBinding {
+                name: name.to_string(),
+                range: None,
+                typ: None,
+            })
+            .collect();
+
+        let symbols = compute_document_symbols(&uri, bindings, &exprs);
+
+        assert_eq!(symbol_kind(&symbols, "zonk"), SymbolKind::FUNCTION);
+        assert_eq!(symbol_kind(&symbols, "greeting"), SymbolKind::STRING);
+        assert_eq!(symbol_kind(&symbols, "unknown"), SymbolKind::VARIABLE);
+
+        let bindings = syntactic_bindings(&exprs);
+        assert_eq!(bindings.len(), 2);
+        assert!(bindings.iter().all(|binding| binding.range.is_some()));
+    }
+
+    #[test]
+    fn compute_document_symbol_response_nested_or_flat() {
+        let input = r#"
+        (let a 1)
+        (let zonk (Func [x y] (+ x y)))
+        "#;
+
+        let uri = Uri::from_str("file:///project/main.tan").unwrap();
+        let exprs = parse_string_all(input).unwrap();
+
+        // #insight before the analysis completes, the syntactic bindings are used.
+        let DocumentSymbolResponse::Nested(symbols) =
+            compute_document_symbol_response(&uri, &exprs, None, true)
+        else {
+            panic!("expected nested symbols");
+        };
+        let zonk = symbols.iter().find(|s| s.name == "zonk").unwrap();
+        assert_eq!(zonk.kind, SymbolKind::FUNCTION);
+        assert_eq!(zonk.selection_range, zonk.range);
+        assert!(zonk.children.is_none());
+
+        let analysis = analyze_module(
+            input.to_string(),
+            uri.to_string(),
+            Vec::new(),
+            &EvalConfig::default(),
+            &Cancellation::default(),
+        )
+        .unwrap();
+
+        // #insight clients without hierarchical support get flat symbols.
+        let DocumentSymbolResponse::Flat(infos) =
+            compute_document_symbol_response(&uri, &exprs, Some(analysis.bindings), false)
+        else {
+            panic!("expected flat symbols");
+        };
+        assert_eq!(infos.len(), symbols.len());
+        assert_eq!(symbol_kind(&infos, "zonk"), SymbolKind::FUNCTION);
+    }
+}
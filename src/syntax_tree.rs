@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+use lsp_types::{request::Request, Position, Range, TextDocumentIdentifier};
+use serde::{Deserialize, Serialize};
+use tan::expr::Expr;
+
+use crate::util::{is_position_in_range, lsp_range_from_tan_range, parse_string_all};
+
+// #insight
+// The syntax tree is a debugging aid, e.g. for range bugs. It shows the
+// analysis-parser AST exactly, including the (missing) ranges.
+
+#[derive(Debug)]
+pub enum SyntaxTree {}
+
+impl Request for SyntaxTree {
+    type Params = SyntaxTreeParams;
+    type Result = Option<SyntaxTreeResult>;
+    const METHOD: &'static str = "tan/syntaxTree";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntaxTreeParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The cursor position, selects the innermost node.
+    pub position: Option<Position>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntaxNode {
+    pub kind: String,
+    /// The printed value of atoms.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    pub range: Option<Range>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<SyntaxNode>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntaxTreeResult {
+    pub nodes: Vec<SyntaxNode>,
+    /// The child indices of the innermost node at the cursor position.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Vec<usize>>,
+    /// The parse errors, there are no nodes if the document is invalid.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+fn expr_kind(expr: &Expr) -> &'static str {
+    if expr.as_list().is_some() {
+        "List"
+    } else if expr.as_symbol().is_some() {
+        "Symbol"
+    } else if expr.as_string().is_some() {
+        "String"
+    } else if expr.as_int().is_some() {
+        "Int"
+    } else if expr.as_float().is_some() {
+        "Float"
+    } else if expr.as_bool().is_some() {
+        "Bool"
+    } else {
+        match expr.unpack() {
+            Expr::None => "None",
+            Expr::Type(_) => "Type",
+            Expr::Array(_) => "Array",
+            Expr::Map(_) => "Map",
+            // #todo add more kinds, as needed.
+            _ => "Other",
+        }
+    }
+}
+
+pub fn syntax_node(expr: &Expr) -> SyntaxNode {
+    let annotations = expr
+        .annotations()
+        .map(|annotations| {
+            annotations
+                .iter()
+                .map(|(name, value)| (name.clone(), value.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let (text, children) = match expr.as_list() {
+        Some(terms) => (None, terms.iter().map(syntax_node).collect()),
+        None => (Some(expr.unpack().to_string()), Vec::new()),
+    };
+
+    SyntaxNode {
+        kind: expr_kind(expr).to_string(),
+        text,
+        annotations,
+        range: expr.range().map(lsp_range_from_tan_range),
+        children,
+    }
+}
+
+/// Returns the child indices of the innermost node that contains the
+/// position.
+pub fn select_node(nodes: &[SyntaxNode], position: Position) -> Option<Vec<usize>> {
+    let (index, node) = nodes.iter().enumerate().find(|(_, node)| {
+        node.range
+            .is_some_and(|range| is_position_in_range(position, range))
+    })?;
+
+    let mut selection = vec![index];
+    if let Some(path) = select_node(&node.children, position) {
+        selection.extend(path);
+    }
+
+    Some(selection)
+}
+
+pub fn compute_syntax_tree(input: &str, position: Option<Position>) -> SyntaxTreeResult {
+    match parse_string_all(input) {
+        Ok(exprs) => {
+            let nodes: Vec<SyntaxNode> = exprs.iter().map(syntax_node).collect();
+            let selection = position.and_then(|position| select_node(&nodes, position));
+            SyntaxTreeResult {
+                nodes,
+                selection,
+                errors: Vec::new(),
+            }
+        }
+        Err(errors) => SyntaxTreeResult {
+            nodes: Vec::new(),
+            selection: None,
+            errors: errors.iter().map(|error| error.to_string()).collect(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Position;
+
+    use crate::syntax_tree::compute_syntax_tree;
+
+    #[test]
+    fn compute_syntax_tree_usage() {
+        let input = "(let a 1)\n(let b (+ a 2))\n";
+
+        let result = compute_syntax_tree(input, Some(Position::new(1, 10)));
+        assert_eq!(result.nodes.len(), 2);
+        assert!(result.errors.is_empty());
+
+        let node = &result.nodes[1];
+        assert_eq!(node.kind, "List");
+        assert_eq!(node.children[0].kind, "Symbol");
+        assert_eq!(node.children[0].text.as_deref(), Some("let"));
+        assert_eq!(node.children[2].kind, "List");
+
+        // #insight the cursor is on `a`, in `(+ a 2)`.
+        assert_eq!(result.selection, Some(vec![1, 2, 1]));
+
+        let result = compute_syntax_tree("(let a", None);
+        assert!(result.nodes.is_empty());
+        assert!(!result.errors.is_empty());
+    }
+}
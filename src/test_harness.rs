@@ -0,0 +1,705 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Exit, Initialized,
+        Notification as LspNotification, PublishDiagnostics,
+    },
+    request::{Initialize, Request as LspRequest, Shutdown},
+    Diagnostic, DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    PublishDiagnosticsParams, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+    TextDocumentItem, Uri, VersionedTextDocumentIdentifier,
+};
+use serde_json::{json, Value};
+
+use crate::{lifecycle::ShutdownStatus, server::Server, util::uri_from_path};
+
+// #insight
+// The harness drives a `Server` over an in-memory connection, like an editor
+// would. The snapshots are committed in `tests/fixtures/snapshots`, a missing
+// snapshot fails the test. Run the tests with `UPDATE_SNAPSHOTS=1` to (re-)write
+// the snapshots, review and commit them.
+
+const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct TestClient {
+    connection: Connection,
+    server: Option<JoinHandle<anyhow::Result<ShutdownStatus>>>,
+    /// Messages received while waiting for other messages.
+    pending: VecDeque<Message>,
+    next_id: i32,
+}
+
+impl TestClient {
+    /// Starts a server, and initializes it with minimal client capabilities.
+    pub fn start() -> Self {
+        Self::start_with(json!({
+            "processId": null,
+            "rootUri": null,
+            "capabilities": {},
+        }))
+    }
+
+    /// Starts a server, and initializes it with the given `initialize` params.
+    pub fn start_with(initialize_params: Value) -> Self {
+        let (server_connection, connection) = Connection::memory();
+
+        let server = std::thread::spawn(move || Server::new().serve(server_connection));
+
+        let mut client = Self {
+            connection,
+            server: Some(server),
+            pending: VecDeque::new(),
+            next_id: 0,
+        };
+
+        client.request_value(Initialize::METHOD, initialize_params);
+        client.notify::<Initialized>(lsp_types::InitializedParams {});
+
+        client
+    }
+
+    fn recv(&mut self) -> Message {
+        self.connection
+            .receiver
+            .recv_timeout(RECV_TIMEOUT)
+            .expect("the server should send a message")
+    }
+
+    /// Receives the first message that matches the predicate, other messages
+    /// are kept for subsequent calls.
+    pub fn recv_matching(&mut self, predicate: impl Fn(&Message) -> bool) -> Message {
+        if let Some(index) = self.pending.iter().position(&predicate) {
+            return self.pending.remove(index).unwrap();
+        }
+
+        loop {
+            let msg = self.recv();
+            if predicate(&msg) {
+                return msg;
+            }
+            self.pending.push_back(msg);
+        }
+    }
+
+    pub fn send(&self, msg: Message) {
+        self.connection.sender.send(msg).unwrap();
+    }
+
+    pub fn notify<N: LspNotification>(&self, params: N::Params) {
+        self.send(Message::Notification(Notification::new(
+            N::METHOD.to_owned(),
+            params,
+        )));
+    }
+
+    /// Sends a request, and waits for the response.
+    pub fn request_value(&mut self, method: &str, params: Value) -> Response {
+        self.next_id += 1;
+        let id = RequestId::from(self.next_id);
+
+        self.send(Message::Request(Request::new(
+            id.clone(),
+            method.to_owned(),
+            params,
+        )));
+
+        let Message::Response(resp) =
+            self.recv_matching(|msg| matches!(msg, Message::Response(resp) if resp.id == id))
+        else {
+            unreachable!();
+        };
+
+        resp
+    }
+
+    /// Sends a request, and returns the (successful) result.
+    pub fn request<R: LspRequest>(&mut self, params: R::Params) -> R::Result {
+        let resp = self.request_value(R::METHOD, serde_json::to_value(params).unwrap());
+
+        if let Some(error) = resp.error {
+            panic!("{} failed: {}", R::METHOD, error.message);
+        }
+
+        serde_json::from_value(resp.result.unwrap_or(Value::Null)).unwrap()
+    }
+
+    pub fn did_open(&self, uri: &Uri, text: &str) {
+        self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                String::from("tan"),
+                1,
+                text.to_string(),
+            ),
+        });
+    }
+
+    pub fn did_change(&self, uri: &Uri, version: i32, text: &str) {
+        self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: text.to_string(),
+            }],
+        });
+    }
+
+    pub fn did_close(&self, uri: &Uri) {
+        self.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+        });
+    }
+
+    /// Waits for the diagnostics of the given document.
+    pub fn recv_diagnostics(&mut self, uri: &Uri) -> Vec<Diagnostic> {
+        let Message::Notification(notification) = self.recv_matching(|msg| {
+            matches!(msg, Message::Notification(notification)
+                if notification.method == PublishDiagnostics::METHOD
+                    && notification.params["uri"] == uri.as_str())
+        }) else {
+            unreachable!();
+        };
+
+        let params: PublishDiagnosticsParams = serde_json::from_value(notification.params).unwrap();
+        params.diagnostics
+    }
+
+    /// Sends `exit`, and waits for the server to exit.
+    pub fn exit(mut self) -> ShutdownStatus {
+        self.notify::<Exit>(());
+
+        let server = self.server.take().unwrap();
+        server.join().unwrap().unwrap()
+    }
+
+    /// Shuts down the server, and waits for the server to exit.
+    pub fn shutdown(mut self) {
+        self.request_value(Shutdown::METHOD, Value::Null);
+        assert_eq!(self.exit(), ShutdownStatus::Clean);
+    }
+}
+
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+pub fn fixture_uri(name: &str) -> Uri {
+    uri_from_path(&fixtures_dir().join(name)).unwrap()
+}
+
+/// Replaces the machine-specific fixtures URI, e.g. in serialized locations.
+pub fn normalize_fixture_uris(text: &str) -> String {
+    let fixtures_uri = uri_from_path(&fixtures_dir()).unwrap();
+    text.replace(fixtures_uri.as_str(), "file:///fixtures")
+}
+
+/// Compares the text with the snapshot, the snapshot is written if
+/// `UPDATE_SNAPSHOTS` is set.
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = fixtures_dir().join("snapshots").join(name);
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        eprintln!("wrote snapshot {}", path.display());
+        return;
+    }
+
+    let Ok(expected) = std::fs::read_to_string(&path) else {
+        panic!("missing snapshot `{name}`, run the tests with `UPDATE_SNAPSHOTS=1` to write it");
+    };
+    assert_eq!(actual, expected, "snapshot `{name}` does not match");
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_server::{Message, Response};
+    use lsp_types::{
+        notification::{
+            DidChangeWatchedFiles, Notification, Progress, PublishDiagnostics,
+            WorkDoneProgressCancel,
+        },
+        request::{
+            DocumentDiagnosticRequest, DocumentSymbolRequest, Formatting, GotoDefinition,
+            RegisterCapability, Request, Shutdown, WorkDoneProgressCreate,
+        },
+        DiagnosticSeverity, DidChangeWatchedFilesParams, DocumentDiagnosticParams,
+        DocumentDiagnosticReport, DocumentDiagnosticReportResult, DocumentFormattingParams,
+        DocumentSymbolParams, FileChangeType, FileEvent, FormattingOptions, GotoDefinitionParams,
+        Position, ProgressParams, ProgressParamsValue, TextDocumentIdentifier,
+        TextDocumentPositionParams, WorkDoneProgress, WorkDoneProgressCancelParams,
+        WorkDoneProgressCreateParams,
+    };
+    use serde_json::Value;
+
+    use crate::{
+        lifecycle::ShutdownStatus,
+        project_config::PROJECT_CONFIG_FILE_NAME,
+        status::{PublishServerStatus, PublishServerStatusParams, ServerState},
+        test_harness::{
+            assert_snapshot, fixture_uri, fixtures_dir, normalize_fixture_uris, TestClient,
+        },
+        util::{parse_string_all, uri_from_path},
+    };
+
+    #[test]
+    fn server_publishes_diagnostics_on_open_and_change() {
+        let mut client = TestClient::start();
+
+        let uri = fixture_uri("scratch.tan");
+        client.did_open(&uri, "(let a 1)\n");
+        assert!(client.recv_diagnostics(&uri).is_empty());
+
+        client.did_change(&uri, 2, "(let a 1\n");
+        let diagnostics = client.recv_diagnostics(&uri);
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.severity == Some(DiagnosticSeverity::ERROR)));
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn server_clears_the_diagnostics_of_closed_documents() {
+        let mut client = TestClient::start();
+
+        let uri = fixture_uri("scratch.tan");
+        client.did_open(&uri, "(let a 1\n");
+        assert!(!client.recv_diagnostics(&uri).is_empty());
+        status_matching(&mut client, |params| params.status.diagnostics > 0);
+
+        client.did_close(&uri);
+        assert!(client.recv_diagnostics(&uri).is_empty());
+        let params = status_matching(&mut client, |params| params.status.diagnostics == 0);
+        assert_eq!(params.status.state, ServerState::Ready);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn server_publishes_analysis_diagnostics_in_background() {
+        let mut client = TestClient::start();
+
+        let uri = fixture_uri("scratch.tan");
+        client.did_open(&uri, "(let a 1)\n(let b (undefined-func a))\n");
+
+        // #insight the lint diagnostics are published first, the analysis
+        // diagnostics once the (debounced) analysis completes.
+        let mut diagnostics = client.recv_diagnostics(&uri);
+        if diagnostics.is_empty() {
+            diagnostics = client.recv_diagnostics(&uri);
+        }
+        assert!(!diagnostics.is_empty());
+
+        // #insight unknown documents have no symbols, the request is answered.
+        let symbols = client.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier::new(fixture_uri("unknown.tan")),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        assert!(symbols.is_none());
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn server_answers_pulled_diagnostics_without_publishing() {
+        let mut client = TestClient::start_with(serde_json::json!({
+            "processId": null,
+            "rootUri": null,
+            "capabilities": { "textDocument": { "diagnostic": {} } },
+        }));
+
+        let uri = fixture_uri("scratch.tan");
+        client.did_open(&uri, "(let a 1\n");
+
+        let report = client.request::<DocumentDiagnosticRequest>(DocumentDiagnosticParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            identifier: None,
+            previous_result_id: None,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(report)) = report
+        else {
+            panic!("expected a full report");
+        };
+        assert!(report
+            .full_document_diagnostic_report
+            .items
+            .iter()
+            .any(|d| d.severity == Some(DiagnosticSeverity::ERROR)));
+
+        // #insight the messages before the response are kept in `pending`,
+        // the diagnostics of `didOpen` are not pushed.
+        assert!(!client.pending.iter().any(|msg| matches!(msg,
+            Message::Notification(notification)
+                if notification.method == PublishDiagnostics::METHOD)));
+
+        client.shutdown();
+    }
+
+    fn status(client: &mut TestClient) -> PublishServerStatusParams {
+        let Message::Notification(notification) = client.recv_matching(|msg| {
+            matches!(msg, Message::Notification(notification)
+                if notification.method == PublishServerStatus::METHOD)
+        }) else {
+            unreachable!();
+        };
+        serde_json::from_value(notification.params).unwrap()
+    }
+
+    /// Receives the statuses until one matches the predicate.
+    fn status_matching(
+        client: &mut TestClient,
+        predicate: impl Fn(&PublishServerStatusParams) -> bool,
+    ) -> PublishServerStatusParams {
+        loop {
+            let params = status(client);
+            if predicate(&params) {
+                return params;
+            }
+        }
+    }
+
+    #[test]
+    fn server_publishes_structured_status() {
+        let mut client = TestClient::start();
+
+        assert_eq!(status(&mut client).status.state, ServerState::Starting);
+
+        let params = status(&mut client);
+        assert_eq!(params.status.state, ServerState::Ready);
+        assert_eq!(params.text, "👅 ready");
+
+        let uri = fixture_uri("scratch.tan");
+        client.did_open(&uri, "(let a 1\n");
+
+        let params = status(&mut client);
+        // #insight open documents outside of the workspace are not indexed.
+        assert_eq!(params.status.indexed_files, 0);
+        assert!(params.status.diagnostics > 0);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn server_publishes_indexed_workspace_files() {
+        let root = std::env::temp_dir().join(format!("tan-ls-indexed-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.tan"), "(let a 1)\n").unwrap();
+        // #insight invalid files are not indexed.
+        std::fs::write(root.join("b.tan"), "(let b\n").unwrap();
+
+        let mut client = TestClient::start_with(serde_json::json!({
+            "processId": null,
+            "rootUri": uri_from_path(&root).unwrap().as_str(),
+            "capabilities": {},
+        }));
+
+        let uri = uri_from_path(&root.join("a.tan")).unwrap();
+        client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri),
+                Position::new(0, 5),
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+
+        let params = status_matching(&mut client, |params| params.status.indexed_files > 0);
+        assert_eq!(params.status.indexed_files, 1);
+
+        client.shutdown();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn server_updates_the_index_on_watched_file_changes() {
+        let root = std::env::temp_dir().join(format!("tan-ls-watched-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.tan"), "(let a 1)\n").unwrap();
+
+        let mut client = TestClient::start_with(serde_json::json!({
+            "processId": null,
+            "rootUri": uri_from_path(&root).unwrap().as_str(),
+            "capabilities": {
+                "workspace": { "didChangeWatchedFiles": { "dynamicRegistration": true } },
+            },
+        }));
+
+        let Message::Request(register) = client.recv_matching(
+            |msg| matches!(msg, Message::Request(req) if req.method == RegisterCapability::METHOD),
+        ) else {
+            unreachable!();
+        };
+        assert!(register.params.to_string().contains("**/*.tan"));
+        client.send(Message::Response(Response::new_ok(
+            register.id,
+            Value::Null,
+        )));
+
+        let uri = uri_from_path(&root.join("a.tan")).unwrap();
+        client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri),
+                Position::new(0, 5),
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let params = status_matching(&mut client, |params| params.status.indexed_files > 0);
+        assert_eq!(params.status.indexed_files, 1);
+
+        // #insight the index is updated on the change, without a request.
+        std::fs::write(root.join("b.tan"), "(let b 2)\n").unwrap();
+        client.notify::<DidChangeWatchedFiles>(DidChangeWatchedFilesParams {
+            changes: vec![FileEvent::new(
+                uri_from_path(&root.join("b.tan")).unwrap(),
+                FileChangeType::CREATED,
+            )],
+        });
+        let params = status_matching(&mut client, |params| params.status.indexed_files > 1);
+        assert_eq!(params.status.indexed_files, 2);
+
+        client.shutdown();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn server_clears_the_project_config_error() {
+        let root = std::env::temp_dir().join(format!("tan-ls-last-error-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut client = TestClient::start_with(serde_json::json!({
+            "processId": null,
+            "rootUri": uri_from_path(&root).unwrap().as_str(),
+            "capabilities": {},
+        }));
+
+        let uri = uri_from_path(&root.join(PROJECT_CONFIG_FILE_NAME)).unwrap();
+        client.did_open(&uri, "{}\n");
+
+        client.did_change(&uri, 2, "[1 2 3]\n");
+        let params = status_matching(&mut client, |params| params.status.last_error.is_some());
+        assert_eq!(params.status.state, ServerState::Error);
+
+        client.did_change(&uri, 3, "{ :lints { :rules { :snake-case :off } } }\n");
+        let params = status_matching(&mut client, |params| params.status.last_error.is_none());
+        assert_eq!(params.status.state, ServerState::Ready);
+
+        client.shutdown();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn server_keeps_the_settings_error_on_project_config_changes() {
+        let root =
+            std::env::temp_dir().join(format!("tan-ls-settings-error-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut client = TestClient::start_with(serde_json::json!({
+            "processId": null,
+            "rootUri": uri_from_path(&root).unwrap().as_str(),
+            "capabilities": {},
+            "initializationOptions": { "eval": { "timeoutMs": "slow" } },
+        }));
+
+        let uri = uri_from_path(&root.join(PROJECT_CONFIG_FILE_NAME)).unwrap();
+        client.did_open(&uri, "{}\n");
+
+        client.did_change(&uri, 2, "[1 2 3]\n");
+        status_matching(&mut client, |params| {
+            params.status.state == ServerState::Error
+        });
+
+        // #insight only the project config error is cleared.
+        client.did_change(&uri, 3, "{}\n");
+        let params = status_matching(&mut client, |params| {
+            params.status.state == ServerState::Ready
+        });
+        assert!(params
+            .status
+            .last_error
+            .is_some_and(|error| error.contains("eval.timeoutMs")));
+
+        client.shutdown();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn server_stops_indexing_on_progress_cancel() {
+        let root = std::env::temp_dir().join(format!("tan-ls-cancel-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        for i in 0..20 {
+            std::fs::write(root.join(format!("module-{i}.tan")), "(let a 1)\n").unwrap();
+        }
+
+        let mut client = TestClient::start_with(serde_json::json!({
+            "processId": null,
+            "rootUri": uri_from_path(&root).unwrap().as_str(),
+            "capabilities": { "window": { "workDoneProgress": true } },
+        }));
+
+        // #insight the request (without a `workDoneToken`) indexes the
+        // workspace, the response is received after the cancellation.
+        let uri = uri_from_path(&root.join("module-0.tan")).unwrap();
+        let id = lsp_server::RequestId::from(1000);
+        client.send(Message::Request(lsp_server::Request::new(
+            id.clone(),
+            GotoDefinition::METHOD.to_owned(),
+            GotoDefinitionParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri),
+                    Position::new(0, 5),
+                ),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        )));
+
+        let Message::Request(create) = client.recv_matching(|msg| {
+            matches!(msg, Message::Request(req) if req.method == WorkDoneProgressCreate::METHOD)
+        }) else {
+            unreachable!();
+        };
+        let token = serde_json::from_value::<WorkDoneProgressCreateParams>(create.params)
+            .unwrap()
+            .token;
+
+        // #insight the server begins the progress once the token is created,
+        // the cancellation sent before is the sync point.
+        client.notify::<WorkDoneProgressCancel>(WorkDoneProgressCancelParams {
+            token: token.clone(),
+        });
+        client.send(Message::Response(Response::new_ok(create.id, Value::Null)));
+
+        client.recv_matching(|msg| matches!(msg, Message::Response(resp) if resp.id == id));
+
+        let progress: Vec<WorkDoneProgress> = client
+            .pending
+            .iter()
+            .filter_map(|msg| match msg {
+                Message::Notification(notification) if notification.method == Progress::METHOD => {
+                    serde_json::from_value::<ProgressParams>(notification.params.clone()).ok()
+                }
+                _ => None,
+            })
+            .filter(|params| params.token == token)
+            .map(|params| {
+                let ProgressParamsValue::WorkDone(value) = params.value;
+                value
+            })
+            .collect();
+
+        // #insight no file is indexed after the cancellation.
+        assert!(matches!(progress[0], WorkDoneProgress::Begin(_)));
+        assert!(!progress
+            .iter()
+            .any(|value| matches!(value, WorkDoneProgress::Report(_))));
+        assert!(matches!(progress.last(), Some(WorkDoneProgress::End(end))
+            if end.message.as_deref() == Some("cancelled")));
+
+        client.shutdown();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn server_rejects_requests_after_shutdown() {
+        let mut client = TestClient::start();
+
+        let resp = client.request_value(Shutdown::METHOD, Value::Null);
+        assert!(resp.error.is_none());
+
+        let resp = client.request_value(Shutdown::METHOD, Value::Null);
+        assert!(resp.error.is_some());
+
+        assert_eq!(client.exit(), ShutdownStatus::Clean);
+    }
+
+    #[test]
+    fn server_exits_with_failure_without_shutdown() {
+        let client = TestClient::start();
+        assert_eq!(client.exit(), ShutdownStatus::Unexpected);
+    }
+
+    #[test]
+    fn server_fixture_snapshots() {
+        let mut names: Vec<String> = std::fs::read_dir(fixtures_dir())
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".tan"))
+            .collect();
+        names.sort();
+
+        let mut client = TestClient::start();
+
+        for name in names {
+            let uri = fixture_uri(&name);
+            let input = std::fs::read_to_string(fixtures_dir().join(&name)).unwrap();
+            let stem = name.trim_end_matches(".tan");
+
+            client.did_open(&uri, &input);
+
+            let diagnostics = client.recv_diagnostics(&uri);
+            assert_snapshot(
+                &format!("{stem}.diagnostics.json"),
+                &serde_json::to_string_pretty(&diagnostics).unwrap(),
+            );
+
+            // #insight the symbols are typed once the background analysis
+            // completes, documents with parse errors are not analyzed.
+            if parse_string_all(&input).is_ok() {
+                status_matching(&mut client, |params| params.text == "👅 analyzed");
+            }
+
+            let symbols = client.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            });
+            assert_snapshot(
+                &format!("{stem}.symbols.json"),
+                &normalize_fixture_uris(&serde_json::to_string_pretty(&symbols).unwrap()),
+            );
+
+            // #insight documents with parse errors cannot be formatted, the
+            // request fails and the server keeps running.
+            if parse_string_all(&input).is_err() {
+                let resp = client.request_value(
+                    Formatting::METHOD,
+                    serde_json::to_value(DocumentFormattingParams {
+                        text_document: TextDocumentIdentifier::new(uri.clone()),
+                        options: FormattingOptions::default(),
+                        work_done_progress_params: Default::default(),
+                    })
+                    .unwrap(),
+                );
+                let error = resp.error.expect("formatting should fail");
+                assert_eq!(error.code, lsp_server::ErrorCode::RequestFailed as i32);
+            } else {
+                let edits = client.request::<Formatting>(DocumentFormattingParams {
+                    text_document: TextDocumentIdentifier::new(uri.clone()),
+                    options: FormattingOptions::default(),
+                    work_done_progress_params: Default::default(),
+                });
+                let formatted = edits
+                    .and_then(|edits| edits.into_iter().next())
+                    .map(|edit| edit.new_text)
+                    .unwrap_or_default();
+                assert_snapshot(&format!("{stem}.formatted.tan"), &formatted);
+            }
+        }
+
+        client.shutdown();
+    }
+}
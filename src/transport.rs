@@ -0,0 +1,120 @@
+use std::{
+    io::{self, BufReader, Read, Write},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use lsp_server::{Connection, IoThreads, Message};
+
+// #insight
+// The server communicates over stdio by default. The TCP transports are useful
+// to attach a debugger to a long-running server, the editor connects to the
+// `--listen` port (or the server connects to the editor with `--connect`).
+// The named-pipe transport is used by the VS Code `TransportKind.pipe`, on
+// Unix the 'pipe' is a domain socket.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Stdio,
+    /// Listen for a client on the given TCP port.
+    Listen(u16),
+    /// Connect to a client at the given TCP address.
+    Connect(String),
+    /// Connect to a client at the given named pipe (or Unix domain socket).
+    Pipe(String),
+}
+
+pub enum TransportThreads {
+    Lsp(IoThreads),
+    Stream(JoinHandle<io::Result<()>>, JoinHandle<io::Result<()>>),
+}
+
+impl TransportThreads {
+    pub fn join(self) -> io::Result<()> {
+        match self {
+            TransportThreads::Lsp(io_threads) => io_threads.join(),
+            TransportThreads::Stream(reader, writer) => {
+                reader
+                    .join()
+                    .map_err(|_| io::Error::other("reader thread panicked"))??;
+                writer
+                    .join()
+                    .map_err(|_| io::Error::other("writer thread panicked"))?
+            }
+        }
+    }
+
+    /// Waits for the threads to end, at most for the given duration.
+    pub fn join_timeout(self, timeout: Duration) -> io::Result<()> {
+        let (sender, receiver) = bounded(1);
+        std::thread::spawn(move || {
+            let _ = sender.send(self.join());
+        });
+
+        receiver
+            .recv_timeout(timeout)
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "join timed out"))?
+    }
+}
+
+/// Creates a connection over a bidirectional stream.
+fn stream_connection<R, W>(reader: R, mut writer: W) -> (Connection, TransportThreads)
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let (reader_sender, reader_receiver): (Sender<Message>, Receiver<Message>) = bounded(0);
+    let reader = std::thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        while let Some(msg) = Message::read(&mut reader)? {
+            let is_exit = matches!(&msg, Message::Notification(n) if n.method == "exit");
+            if reader_sender.send(msg).is_err() || is_exit {
+                break;
+            }
+        }
+        Ok(())
+    });
+
+    let (writer_sender, writer_receiver): (Sender<Message>, Receiver<Message>) = bounded(0);
+    let writer = std::thread::spawn(move || {
+        writer_receiver
+            .into_iter()
+            .try_for_each(|msg| msg.write(&mut writer))
+    });
+
+    let connection = Connection {
+        sender: writer_sender,
+        receiver: reader_receiver,
+    };
+
+    (connection, TransportThreads::Stream(reader, writer))
+}
+
+#[cfg(unix)]
+fn pipe_connection(name: &str) -> io::Result<(Connection, TransportThreads)> {
+    let stream = std::os::unix::net::UnixStream::connect(name)?;
+    Ok(stream_connection(stream.try_clone()?, stream))
+}
+
+#[cfg(windows)]
+fn pipe_connection(name: &str) -> io::Result<(Connection, TransportThreads)> {
+    let pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(name)?;
+    Ok(stream_connection(pipe.try_clone()?, pipe))
+}
+
+impl Transport {
+    pub fn connect(&self) -> io::Result<(Connection, TransportThreads)> {
+        let (connection, io_threads) = match self {
+            Transport::Stdio => Connection::stdio(),
+            Transport::Listen(port) => Connection::listen(("127.0.0.1", *port))?,
+            Transport::Connect(addr) => Connection::connect(addr.as_str())?,
+            Transport::Pipe(name) => return pipe_connection(name),
+        };
+
+        Ok((connection, TransportThreads::Lsp(io_threads)))
+    }
+}
@@ -1,9 +1,11 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 
-use serde::{Deserialize, Serialize};
-
 use lsp_server::{Connection, Message};
-use lsp_types::notification::Notification;
+use lsp_types::notification::{LogMessage, Notification};
+use lsp_types::{LogMessageParams, MessageType, Uri};
 
 use tan::api::compile;
 use tan::context::Context;
@@ -16,6 +18,8 @@ use tan::scope::Scope;
 use tan::util::standard_names::CURRENT_MODULE_PATH;
 use tan_formatting::types::Dialect;
 
+use crate::imports::imported_bindings;
+
 use crossbeam::channel::SendError;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -44,30 +48,36 @@ pub fn parse_string_all(input: impl AsRef<str>) -> Result<Vec<Expr>, Vec<Error>>
     Ok(exprs)
 }
 
-#[derive(Debug)]
-pub enum PublishServerStatus {}
+/// Returns the `(name, value)` pairs of a `let` expression, e.g.
+/// `(let a 1 b 2)`.
+pub fn let_bindings(expr: &Expr) -> Vec<(&Expr, &Expr)> {
+    let Some(terms) = expr.as_list() else {
+        return Vec::new();
+    };
 
-impl Notification for PublishServerStatus {
-    type Params = PublishServerStatusParams;
-    const METHOD: &'static str = "tan/publishServerStatus";
-}
+    if terms.first().and_then(|op| op.as_symbol()) != Some("let") {
+        return Vec::new();
+    }
 
-#[derive(Serialize, Deserialize)]
-pub struct PublishServerStatusParams {
-    pub text: String,
+    terms[1..]
+        .chunks(2)
+        .filter_map(|pair| match pair {
+            [name, value] => Some((name, value)),
+            _ => None,
+        })
+        .collect()
 }
 
-pub fn send_server_status_notification(
+pub fn send_log_message_notification(
     connection: &Connection,
-    text: &str,
+    typ: MessageType,
+    message: String,
 ) -> Result<(), SendError<Message>> {
-    let text = format!("👅 {text}");
-
-    let pss = PublishServerStatusParams { text };
+    let params = LogMessageParams { typ, message };
 
     let notification = lsp_server::Notification {
-        method: PublishServerStatus::METHOD.to_owned(),
-        params: serde_json::to_value(pss).unwrap(),
+        method: LogMessage::METHOD.to_owned(),
+        params: serde_json::to_value(params).unwrap(),
     };
 
     connection
@@ -83,7 +93,6 @@ pub fn lsp_range_top() -> lsp_types::Range {
     lsp_types::Range::new(start, start)
 }
 
-#[allow(dead_code)]
 pub fn lsp_range_whole_document() -> lsp_types::Range {
     let start = lsp_types::Position::new(0, 0);
     let end = lsp_types::Position::new(u32::MAX, u32::MAX);
@@ -91,6 +100,8 @@ pub fn lsp_range_whole_document() -> lsp_types::Range {
 }
 
 // #todo move this helper to tan-analysis
+// #insight the character positions are converted to the negotiated encoding
+// at the boundary with the client, see `line_index`.
 pub fn lsp_range_from_tan_range(tan_range: tan::range::Range) -> lsp_types::Range {
     let start = lsp_types::Position {
         line: tan_range.start.line as u32,
@@ -103,17 +114,53 @@ pub fn lsp_range_from_tan_range(tan_range: tan::range::Range) -> lsp_types::Rang
     lsp_types::Range { start, end }
 }
 
+pub fn is_position_in_range(position: lsp_types::Position, range: lsp_types::Range) -> bool {
+    position >= range.start && position <= range.end
+}
+
+// #todo percent-encode the path.
+pub fn uri_from_path(path: &Path) -> Option<Uri> {
+    Uri::from_str(&format!("file://{}", path.display())).ok()
+}
+
+// #todo percent-decode the path.
+pub fn path_from_uri(uri: &str) -> Option<PathBuf> {
+    uri.strip_prefix("file://").map(PathBuf::from)
+}
+
+/// Returns the module path of a document, i.e. the directory that contains
+/// the document. Falls back to the current directory for non-file URIs.
+pub fn document_module_path(uri: &str) -> Result<PathBuf, std::io::Error> {
+    if let Some(dir) = path_from_uri(uri).and_then(|path| path.parent().map(Path::to_path_buf)) {
+        return Ok(dir);
+    }
+
+    std::env::current_dir()
+}
+
+/// Returns the `CURRENT_MODULE_PATH` of the given context.
+pub fn context_module_path(context: &Context) -> Option<PathBuf> {
+    let bindings = context.top_scope.bindings.read().expect("not poisoned");
+    let path = bindings.get(CURRENT_MODULE_PATH)?.as_string()?;
+    Some(PathBuf::from(path))
+}
+
+// #insight
+// A fresh context is created for every analysis of a document, so that
+// relative `use` paths are resolved against the directory of the document and
+// scopes do not leak between documents or requests.
+
 // #insight used to initialize current_module_path.
 // #todo find a better name.
 // #todo extract this helper function, it's useful in multiple places.
-pub fn make_analysis_context() -> Result<Context, std::io::Error> {
+pub fn make_analysis_context(document_uri: &str) -> Result<Context, std::io::Error> {
     let context = Context::new();
 
-    let current_dir = std::env::current_dir()?.display().to_string();
+    let module_path = document_module_path(document_uri)?.display().to_string();
 
     context
         .top_scope
-        .insert(CURRENT_MODULE_PATH, Expr::string(current_dir));
+        .insert(CURRENT_MODULE_PATH, Expr::string(module_path));
 
     Ok(context)
 }
@@ -122,9 +169,53 @@ pub fn make_analysis_context() -> Result<Context, std::io::Error> {
 // #todo find a better name.
 // #todo return the binding in more useful/processed format
 // #todo use a fully initialized context.
-pub fn parse_module_file(exprs: &[Expr], context: &mut Context) -> Result<Arc<Scope>, Vec<Error>> {
+// #insight
+// Compile and eval errors are returned alongside the scope, to be reported as
+// diagnostics. Use `analyze_module` to run the analysis in a sandboxed,
+// time-limited context.
+pub fn parse_module_file(
+    exprs: &[Expr],
+    context: &mut Context,
+    search_paths: &[PathBuf],
+) -> (Arc<Scope>, Vec<Error>) {
     // #todo implement some context nesting helpers.
-    context.scope = Arc::new(Scope::new(context.scope.clone()));
+
+    // #insight imported bindings live in a separate parent scope, so that the
+    // returned scope only contains the bindings defined in the module itself.
+    // #insight the scope only needs the values, goto-definition resolves the
+    // source locations of imported bindings with `imported_bindings`.
+    let imports_scope = Arc::new(Scope::new(context.scope.clone()));
+
+    if let Some(current_module_path) = context_module_path(context) {
+        // #insight the imported values are evaluated in a scope per module, with
+        // the unqualified names of the module, e.g. an imported `Func` is a
+        // function, not its definition. The errors belong to the imported
+        // module, the unevaluated value is kept instead.
+        let mut module_scopes: HashMap<PathBuf, Arc<Scope>> = HashMap::new();
+        let document_scope = context.scope.clone();
+
+        for binding in imported_bindings(exprs, &current_module_path, search_paths) {
+            let module_scope = module_scopes
+                .entry(binding.path.clone())
+                .or_insert_with(|| Arc::new(Scope::new(document_scope.clone())))
+                .clone();
+
+            context.scope = module_scope.clone();
+            let value = compile(expr_clone(&binding.value), context)
+                .ok()
+                .and_then(|expr| eval(&expr, context).ok())
+                .unwrap_or(binding.value);
+
+            if let Some((_, name)) = binding.name.split_once('/') {
+                module_scope.insert(name, expr_clone(&value));
+            }
+            imports_scope.insert(binding.name, value);
+        }
+
+        context.scope = document_scope;
+    }
+
+    context.scope = Arc::new(Scope::new(imports_scope));
 
     // #todo #IMPORTANT I think eval is _not_ really needed! maybe just compile!
     // let _ = eval_string(input, context);
@@ -146,10 +237,12 @@ pub fn parse_module_file(exprs: &[Expr], context: &mut Context) -> Result<Arc<Sc
     // let compiled_exprs = exprs;
 
     // #insight only process top-level `let` definitions.
-    // #insight ignore problematic `use` imports.
+    // #insight `use` imports are statically indexed above, not evaluated.
 
     // #todo implement a formalized method for custom evaluators like the following.
 
+    let mut errors = Vec::new();
+
     for expr in exprs {
         if let Some(terms) = expr.as_list() {
             if let Some(op) = terms.first() {
@@ -157,24 +250,45 @@ pub fn parse_module_file(exprs: &[Expr], context: &mut Context) -> Result<Arc<Sc
                     if sym == "let" {
                         // #todo why do we need this compile? seems that the function def is not handled.
                         // #todo #IMPORTANT remove this fucking clone!
-                        let expr = compile(expr_clone(expr), context)?;
-                        // #todo what to do about the error case here?
+                        let expr = match compile(expr_clone(expr), context) {
+                            Ok(expr) => expr,
+                            Err(mut compile_errors) => {
+                                errors.append(&mut compile_errors);
+                                continue;
+                            }
+                        };
                         // let _ = tan::eval::eval_let::eval_let(op, &terms[1..], context);
-                        let _ = eval(&expr, context);
+                        if let Err(error) = eval(&expr, context) {
+                            errors.push(error);
+                        }
                     }
                 }
             }
         }
     }
 
-    Ok(context.scope.clone())
+    (context.scope.clone(), errors)
 }
 
 #[cfg(test)]
 mod tests {
-    use tan::context::Context;
+    use std::path::PathBuf;
+
+    use tan::{context::Context, expr::Expr};
 
-    use crate::util::{parse_module_file, parse_string_all};
+    use crate::util::{
+        context_module_path, make_analysis_context, parse_module_file, parse_string_all,
+        uri_from_path,
+    };
+
+    #[test]
+    fn make_analysis_context_uses_document_directory() {
+        let context = make_analysis_context("file:///home/user/project/src/main.tan").unwrap();
+        assert_eq!(
+            context_module_path(&context),
+            Some(PathBuf::from("/home/user/project/src"))
+        );
+    }
 
     #[test]
     fn parse_module_file_usage() {
@@ -187,7 +301,7 @@ mod tests {
         "#;
 
         let exprs = parse_string_all(input).unwrap();
-        let scope = parse_module_file(&exprs, &mut context).unwrap();
+        let (scope, _) = parse_module_file(&exprs, &mut context, &[]);
         let bindings = scope.bindings.read().expect("not poisoned");
         let symbols: Vec<String> = bindings.keys().cloned().collect();
         assert!(symbols.contains(&String::from("a")));
@@ -202,14 +316,25 @@ mod tests {
         (let zonk (Func [x y] (+ x y)))
         "#;
 
+        // #insight `/rng` is resolved in the standard library, a stand-in
+        // `@std/rng` module is used.
+        let tan_root = std::env::temp_dir().join(format!("tan-ls-std-lib-{}", std::process::id()));
+        std::fs::create_dir_all(tan_root.join("@std")).unwrap();
+        std::fs::write(tan_root.join("@std/rng.tan"), "(let random (Func [] 4))\n").unwrap();
+        std::env::set_var("TAN_ROOT", &tan_root);
+
+        let mut context = make_analysis_context("file:///project/main.tan").unwrap();
         let exprs = parse_string_all(input).unwrap();
-        let scope = parse_module_file(&exprs, &mut context).unwrap();
+        let (scope, _) = parse_module_file(&exprs, &mut context, &[]);
         let bindings = scope.bindings.read().expect("not poisoned");
         let symbols: Vec<String> = bindings.keys().cloned().collect();
-        // #insight we ignore `use` imports
-        // assert!(symbols.contains(&String::from("rng/random")));
         assert!(symbols.contains(&String::from("b")));
         assert!(symbols.contains(&String::from("zonk")));
+        // #insight the imported bindings are in the parent scope.
+        assert!(scope.get("rng/random").is_some());
+        drop(bindings);
+
+        std::fs::remove_dir_all(&tan_root).unwrap();
 
         let input = r#"
         (let b 2)
@@ -219,9 +344,53 @@ mod tests {
         "#;
 
         let exprs = parse_string_all(input).unwrap();
-        let scope = parse_module_file(&exprs, &mut context).unwrap();
+        let (scope, _) = parse_module_file(&exprs, &mut context, &[]);
         let bindings = scope.bindings.read().expect("not poisoned");
         let symbols: Vec<String> = bindings.keys().cloned().collect();
         assert!(symbols.contains(&String::from("z")));
     }
+
+    #[test]
+    fn parse_module_file_evaluates_imported_bindings() {
+        let dir =
+            std::env::temp_dir().join(format!("tan-ls-parse-module-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("math.tan"),
+            "(let pi 3)\n(let add (Func [x y] (+ x y)))\n(let tau (add pi pi))\n",
+        )
+        .unwrap();
+
+        let uri = uri_from_path(&dir.join("main.tan")).unwrap();
+        let mut context = make_analysis_context(uri.as_str()).unwrap();
+
+        let exprs = parse_string_all("(use ./math)\n(let a (math/add 1 2))\n").unwrap();
+        let (scope, errors) = parse_module_file(&exprs, &mut context, &[]);
+        assert!(errors.is_empty());
+
+        // #insight the imported function is a function, not its definition.
+        let add = scope.get("math/add").unwrap();
+        assert!(matches!(add.dyn_type(&context), Expr::Type(typ) if typ == "Func"));
+        // #insight the module bindings refer to each other, unqualified.
+        assert_eq!(scope.get("math/tau").unwrap().as_int(), Some(6));
+        assert_eq!(scope.get("a").unwrap().as_int(), Some(3));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_module_file_collects_errors() {
+        let mut context = Context::new();
+
+        let input = r#"
+        (let a 1)
+        (let b (undefined-func a))
+        "#;
+
+        let exprs = parse_string_all(input).unwrap();
+        let (scope, errors) = parse_module_file(&exprs, &mut context, &[]);
+        let bindings = scope.bindings.read().expect("not poisoned");
+        assert!(bindings.contains_key("a"));
+        assert!(!errors.is_empty());
+    }
 }
@@ -0,0 +1,230 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use lsp_types::Uri;
+use tan::expr::Expr;
+
+use crate::{
+    config::WorkspaceConfig,
+    parse_cache::ParsedDocument,
+    util::{parse_string_all, uri_from_path},
+};
+
+// #insight
+// The workspace files are parsed once and kept in the `WorkspaceIndex`, a file
+// (valid or not) is re-parsed only if its modification time changes. The open documents are
+// parsed by the `ParsedDocument` cache, and take precedence over the files on
+// disk.
+
+// #insight directories that never contain Tan sources of the workspace.
+const IGNORED_DIRS: [&str; 3] = ["target", "node_modules", ".git"];
+
+/// Recursively collects the `.tan` files of a workspace.
+// #todo consider a .gitignore-aware walker.
+pub fn workspace_files(root: &Path, config: &WorkspaceConfig) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_tan_files(root, config, &mut files);
+    files.sort();
+    files
+}
+
+/// A parsed file of the workspace, the expressions are None for unreadable and
+/// invalid files.
+struct IndexedFile {
+    uri: Uri,
+    modified: Option<SystemTime>,
+    exprs: Option<Vec<Expr>>,
+}
+
+/// The parsed files of the workspace.
+#[derive(Default)]
+pub struct WorkspaceIndex {
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+impl WorkspaceIndex {
+    /// The number of the indexed (valid) files.
+    pub fn file_count(&self) -> usize {
+        self.files
+            .values()
+            .filter(|file| file.exprs.is_some())
+            .count()
+    }
+
+    /// Updates the index to the files of the workspace, only new and modified
+    /// files are parsed. `report` is called with the number of processed and
+    /// total files, the update stops on `Break`. Returns the number of the
+    /// parsed files.
+    pub fn update(
+        &mut self,
+        root: Option<&Path>,
+        config: &WorkspaceConfig,
+        mut report: impl FnMut(usize, usize) -> ControlFlow<()>,
+    ) -> usize {
+        let Some(root) = root else {
+            self.files.clear();
+            return 0;
+        };
+
+        let paths = workspace_files(root, config);
+        let total = paths.len();
+
+        // #insight drop the deleted (or excluded) files.
+        let existing: HashSet<&PathBuf> = paths.iter().collect();
+        self.files.retain(|path, _| existing.contains(path));
+
+        let mut parsed = 0;
+
+        for (i, path) in paths.iter().enumerate() {
+            if report(i, total).is_break() {
+                return parsed;
+            }
+
+            let modified = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+
+            if modified.is_some()
+                && self
+                    .files
+                    .get(path)
+                    .is_some_and(|file| file.modified == modified)
+            {
+                continue;
+            }
+
+            parsed += 1;
+
+            // #insight unreadable and invalid files are kept without
+            // expressions, so that they are not re-read until modified.
+            let exprs = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|input| parse_string_all(input).ok());
+
+            match uri_from_path(path) {
+                Some(uri) => {
+                    self.files.insert(
+                        path.clone(),
+                        IndexedFile {
+                            uri,
+                            modified,
+                            exprs,
+                        },
+                    );
+                }
+                None => {
+                    self.files.remove(path);
+                }
+            }
+        }
+
+        let _ = report(total, total);
+
+        parsed
+    }
+
+    /// Returns the parsed sources of the workspace, the open `documents`
+    /// (keyed by URI) take precedence over the files on disk.
+    pub fn sources<'a>(
+        &'a self,
+        documents: &'a HashMap<String, ParsedDocument>,
+    ) -> Vec<(Uri, &'a [Expr])> {
+        let mut sources = Vec::new();
+
+        for (uri, document) in documents {
+            let (Ok(uri), Ok(exprs)) = (Uri::from_str(uri), document.result()) else {
+                continue;
+            };
+            sources.push((uri, exprs.as_slice()));
+        }
+
+        for file in self.files.values() {
+            let Some(exprs) = &file.exprs else {
+                continue;
+            };
+            if !documents.contains_key(file.uri.as_str()) {
+                sources.push((file.uri.clone(), exprs.as_slice()));
+            }
+        }
+
+        sources
+    }
+}
+
+fn collect_tan_files(dir: &Path, config: &WorkspaceConfig, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if config.is_excluded(&path.to_string_lossy()) {
+            continue;
+        }
+
+        if path.is_dir() {
+            if name.starts_with('.') || IGNORED_DIRS.contains(&name.as_str()) {
+                continue;
+            }
+            collect_tan_files(&path, config, files);
+        } else if name.ends_with(".tan") {
+            files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, ops::ControlFlow};
+
+    use crate::{
+        config::WorkspaceConfig, parse_cache::ParsedDocument, util::uri_from_path,
+        workspace::WorkspaceIndex,
+    };
+
+    #[test]
+    fn workspace_index_usage() {
+        let dir = std::env::temp_dir().join(format!(
+            "tan-language-server-workspace-index-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.tan"), "(let a 1)\n").unwrap();
+        std::fs::write(dir.join("lib.tan"), "(let b 2)\n").unwrap();
+        std::fs::write(dir.join("invalid.tan"), "(let c\n").unwrap();
+
+        let config = WorkspaceConfig::default();
+        let mut index = WorkspaceIndex::default();
+
+        let parsed = index.update(Some(&dir), &config, |_, _| ControlFlow::Continue(()));
+        assert_eq!(parsed, 3);
+        assert_eq!(index.file_count(), 2);
+
+        // #insight unmodified files, valid or not, are not re-parsed.
+        let parsed = index.update(Some(&dir), &config, |_, _| ControlFlow::Continue(()));
+        assert_eq!(parsed, 0);
+
+        // #insight the open documents take precedence.
+        let uri = uri_from_path(&dir.join("main.tan")).unwrap();
+        let documents = HashMap::from([(
+            uri.to_string(),
+            ParsedDocument::new("(let a 1)\n(let d 4)\n"),
+        )]);
+        let sources = index.sources(&documents);
+        assert_eq!(sources.len(), 2);
+        let (_, exprs) = sources
+            .iter()
+            .find(|(source_uri, _)| *source_uri == uri)
+            .unwrap();
+        assert_eq!(exprs.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}